@@ -4,6 +4,7 @@ local h = ngx.req.get_headers()
 local key = h["relayer-api-key"]
 local sig = h["signature"]
 local datetime = h["datetime"]
+local nonce = h["nonce"]
 
 ngx.req.read_body()
 
@@ -13,6 +14,7 @@ local req = {}
 req['api_key'] = key
 req['sig'] = sig
 req['datetime'] = datetime
+req['nonce'] = nonce
 req['body'] = body
 
 local args = { method = ngx.HTTP_POST, body = json.encode(req) }
@@ -0,0 +1,10 @@
+-- Last kafka offset applied by the archiver, per consumer group and partition. Written in the
+-- same transaction as each archived batch.
+CREATE TABLE IF NOT EXISTS archiver_offset (
+    consumer_group VARCHAR NOT NULL,
+    topic VARCHAR NOT NULL,
+    partition_id INT4 NOT NULL,
+    last_offset INT8 NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (consumer_group, topic, partition_id)
+);
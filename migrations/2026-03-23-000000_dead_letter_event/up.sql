@@ -0,0 +1,18 @@
+-- Kafka messages that could not be decoded into an Event. Kept verbatim so they can be replayed
+-- once relayer-core understands them.
+CREATE TABLE IF NOT EXISTS dead_letter_event (
+    id BIGSERIAL PRIMARY KEY,
+    topic VARCHAR NOT NULL,
+    partition_id INT4 NOT NULL,
+    kafka_offset INT8 NOT NULL,
+    event_key VARCHAR NOT NULL,
+    payload TEXT NOT NULL,
+    error TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    replay_requested BOOLEAN NOT NULL DEFAULT false,
+    replayed_at TIMESTAMPTZ,
+    UNIQUE (topic, partition_id, kafka_offset)
+);
+
+CREATE INDEX IF NOT EXISTS idx_dead_letter_event_replay_requested
+ON dead_letter_event (id) WHERE replay_requested;
@@ -0,0 +1,11 @@
+DROP TRIGGER IF EXISTS after_insert_price_trigger_for_candle_data_generation ON btc_usd_price;
+CREATE TRIGGER after_insert_price_trigger_for_candle_data_generation
+    AFTER INSERT
+    ON public.btc_usd_price
+    FOR EACH ROW
+    EXECUTE FUNCTION public.create_price_triger_after_insert_for_candle_data_generation();
+
+DROP FUNCTION IF EXISTS rollup_candles(text, text, timestamptz[]);
+DROP FUNCTION IF EXISTS refresh_minute_candles(timestamptz[], text[]);
+DROP FUNCTION IF EXISTS candle_bucket(timestamptz, interval);
+DROP TABLE IF EXISTS candles;
@@ -0,0 +1,191 @@
+-- Candles for every `Interval`, keyed by resolution ('1 minute', '4 hours', ...). The archiver
+-- keeps them current by refreshing the buckets each committed batch touched, so `candle_data`
+-- only has to read a range of the primary key.
+CREATE TABLE IF NOT EXISTS candles (
+    resolution TEXT NOT NULL,
+    start_time TIMESTAMPTZ NOT NULL,
+    end_time TIMESTAMPTZ NOT NULL,
+    low NUMERIC NOT NULL,
+    high NUMERIC NOT NULL,
+    open NUMERIC NOT NULL,
+    close NUMERIC NOT NULL,
+    trades INT4 NOT NULL,
+    btc_volume NUMERIC NOT NULL,
+    usd_volume NUMERIC NOT NULL,
+    updated_at TIMESTAMPTZ NOT NULL DEFAULT now(),
+    PRIMARY KEY (resolution, start_time)
+);
+
+-- Start of the epoch-aligned bucket containing `ts`, same bucketing as get_ohlc_interval.
+CREATE FUNCTION candle_bucket(ts timestamptz, intvl interval)
+RETURNS timestamptz
+AS $$
+    SELECT to_timestamp(
+        floor(extract(epoch from ts) / extract(epoch from intvl)) * extract(epoch from intvl)
+    )
+$$
+LANGUAGE SQL IMMUTABLE;
+
+
+-- Recompute the 1 minute buckets containing any of `touched` from btc_usd_price and the
+-- trader_order rows whose status is one of `trade_statuses`, which the caller passes so the live
+-- candles and these count the same rows. Buckets are always rebuilt whole, so overlapping or
+-- repeated calls are harmless.
+CREATE FUNCTION refresh_minute_candles(touched timestamptz[], trade_statuses text[])
+RETURNS void
+AS $$
+WITH buckets AS (
+    SELECT DISTINCT candle_bucket(t, '1 minute') as bucket
+    FROM unnest(touched) t
+), ohlc AS (
+    SELECT
+        buckets.bucket,
+        max(price)                                              as high,
+        min(price)                                              as low,
+        (array_agg(price ORDER BY timestamp ASC))[1]            as open,
+        (array_agg(price ORDER BY timestamp DESC))[1]           as close
+    FROM buckets JOIN btc_usd_price
+        ON timestamp >= buckets.bucket AND timestamp < buckets.bucket + interval '1 minute'
+    GROUP BY buckets.bucket
+), volume AS (
+    SELECT
+        buckets.bucket,
+        coalesce(sum(positionsize), 0) / 100000000                              as usd_volume,
+        coalesce(sum(positionsize / nullif(entryprice, 0)), 0) / 100000000      as btc_volume,
+        count(*)::integer                                                       as trades
+    FROM buckets JOIN trader_order
+        ON timestamp >= buckets.bucket AND timestamp < buckets.bucket + interval '1 minute'
+    WHERE order_status::text = ANY(trade_statuses)
+    GROUP BY buckets.bucket
+)
+INSERT INTO candles (
+    resolution, start_time, end_time, low, high, open, close, trades, btc_volume, usd_volume,
+    updated_at
+)
+SELECT
+    '1 minute',
+    coalesce(ohlc.bucket, volume.bucket),
+    coalesce(ohlc.bucket, volume.bucket) + interval '1 minute',
+    coalesce(ohlc.low, 0),
+    coalesce(ohlc.high, 0),
+    coalesce(ohlc.open, 0),
+    coalesce(ohlc.close, 0),
+    coalesce(volume.trades, 0),
+    coalesce(volume.btc_volume, 0),
+    coalesce(volume.usd_volume, 0),
+    now()
+FROM
+    ohlc FULL OUTER JOIN volume
+    ON ohlc.bucket = volume.bucket
+    ON CONFLICT(resolution, start_time)
+    DO UPDATE SET
+    end_time   = excluded.end_time,
+    low        = excluded.low,
+    high       = excluded.high,
+    open       = excluded.open,
+    close      = excluded.close,
+    trades     = excluded.trades,
+    btc_volume = excluded.btc_volume,
+    usd_volume = excluded.usd_volume,
+    updated_at = excluded.updated_at
+    ;
+$$
+LANGUAGE SQL;
+
+
+-- Recompute the `res` buckets containing any of `touched` from the materialized `source` candles,
+-- which must divide `res` and be current for those buckets. Candles without prices (a high of 0)
+-- only add their volume.
+CREATE FUNCTION rollup_candles(res text, source text, touched timestamptz[])
+RETURNS void
+AS $$
+WITH buckets AS (
+    SELECT DISTINCT candle_bucket(t, res::interval) as bucket
+    FROM unnest(touched) t
+)
+INSERT INTO candles (
+    resolution, start_time, end_time, low, high, open, close, trades, btc_volume, usd_volume,
+    updated_at
+)
+SELECT
+    res,
+    buckets.bucket,
+    buckets.bucket + res::interval,
+    coalesce(min(low) FILTER (WHERE high > 0), 0),
+    coalesce(max(high), 0),
+    coalesce((array_agg(open ORDER BY start_time ASC) FILTER (WHERE high > 0))[1], 0),
+    coalesce((array_agg(close ORDER BY start_time DESC) FILTER (WHERE high > 0))[1], 0),
+    sum(trades)::integer,
+    sum(btc_volume),
+    sum(usd_volume),
+    now()
+FROM buckets JOIN candles
+    ON resolution = source
+    AND start_time >= buckets.bucket AND start_time < buckets.bucket + res::interval
+GROUP BY buckets.bucket
+    ON CONFLICT(resolution, start_time)
+    DO UPDATE SET
+    end_time   = excluded.end_time,
+    low        = excluded.low,
+    high       = excluded.high,
+    open       = excluded.open,
+    close      = excluded.close,
+    trades     = excluded.trades,
+    btc_volume = excluded.btc_volume,
+    usd_volume = excluded.usd_volume,
+    updated_at = excluded.updated_at
+    ;
+$$
+LANGUAGE SQL;
+
+
+-- Seed the new table from the 1min/1hour/1day tables so existing history keeps working without a
+-- backfill. Coarser resolutions are rolled up from the finest table that divides them.
+CREATE FUNCTION seed_candles(res text, source regclass)
+RETURNS void
+AS $$
+BEGIN
+    EXECUTE format(
+        $q$
+        INSERT INTO candles (
+            resolution, start_time, end_time, low, high, open, close, trades, btc_volume,
+            usd_volume
+        )
+        SELECT
+            $1,
+            bucket,
+            bucket + $1::interval,
+            min(low),
+            max(high),
+            (array_agg(open ORDER BY start_time ASC))[1],
+            (array_agg(close ORDER BY start_time DESC))[1],
+            sum(trades)::integer,
+            sum(btc_volume),
+            sum(usd_volume)
+        FROM (
+            SELECT candle_bucket(start_time, $1::interval) as bucket, * FROM %s
+        ) t
+        GROUP BY bucket
+        ON CONFLICT(resolution, start_time) DO NOTHING
+        $q$,
+        source
+    ) USING res;
+END;
+$$
+LANGUAGE plpgsql;
+
+SELECT seed_candles('1 minute', 'candles_1min');
+SELECT seed_candles('5 minutes', 'candles_1min');
+SELECT seed_candles('15 minutes', 'candles_1min');
+SELECT seed_candles('30 minutes', 'candles_1min');
+SELECT seed_candles('1 hour', 'candles_1hour');
+SELECT seed_candles('4 hours', 'candles_1hour');
+SELECT seed_candles('8 hours', 'candles_1hour');
+SELECT seed_candles('12 hours', 'candles_1hour');
+SELECT seed_candles('1 day', 'candles_1day');
+
+DROP FUNCTION seed_candles(text, regclass);
+
+-- Rebuilding the 1min/1hour/1day tables on every price insert is what the archiver now does
+-- incrementally, per batch.
+DROP TRIGGER IF EXISTS after_insert_price_trigger_for_candle_data_generation ON btc_usd_price;
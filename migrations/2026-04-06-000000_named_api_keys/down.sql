@@ -0,0 +1,6 @@
+DROP INDEX IF EXISTS idx_customer_apikey_linking_active_customer;
+DROP INDEX IF EXISTS idx_customer_apikey_linking_api_key;
+UPDATE customer_apikey_linking SET expires_on = created_on + INTERVAL '7 days' WHERE expires_on IS NULL;
+ALTER TABLE customer_apikey_linking ALTER COLUMN expires_on SET NOT NULL;
+ALTER TABLE customer_apikey_linking DROP COLUMN IF EXISTS last_used_on;
+ALTER TABLE customer_apikey_linking DROP COLUMN IF EXISTS label;
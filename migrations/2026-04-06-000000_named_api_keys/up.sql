@@ -0,0 +1,10 @@
+-- Customers can hold several api keys, each with a label, the time it was last used and an
+-- optional expiry. Existing keys keep the expiry they were created with.
+ALTER TABLE customer_apikey_linking ADD COLUMN IF NOT EXISTS label VARCHAR NOT NULL DEFAULT 'default';
+ALTER TABLE customer_apikey_linking ADD COLUMN IF NOT EXISTS last_used_on TIMESTAMPTZ;
+ALTER TABLE customer_apikey_linking ALTER COLUMN expires_on DROP NOT NULL;
+
+CREATE INDEX IF NOT EXISTS idx_customer_apikey_linking_api_key
+ON customer_apikey_linking (api_key);
+CREATE INDEX IF NOT EXISTS idx_customer_apikey_linking_active_customer
+ON customer_apikey_linking (customer_account_id) WHERE is_active;
@@ -0,0 +1,11 @@
+-- Encrypted secrets can't be decrypted here. Stop the auth server and run
+-- `auth decrypt-secrets` first, rolling back with encrypted secrets left fails instead of locking
+-- their customers out.
+DO $$
+BEGIN
+    IF EXISTS (SELECT 1 FROM customer_apikey_linking WHERE secret_key_id IS NOT NULL) THEN
+        RAISE EXCEPTION 'api secrets are still encrypted, run `auth decrypt-secrets` first';
+    END IF;
+END
+$$;
+ALTER TABLE customer_apikey_linking DROP COLUMN IF EXISTS secret_key_id;
@@ -0,0 +1,6 @@
+-- Api secrets are stored encrypted under a server key, secret_key_id names the key. The
+-- encryption happens outside SQL and is the second step of this migration: rows left with a NULL
+-- secret_key_id still hold the plaintext secret until the auth service encrypts them when it
+-- starts. Nothing reads a NULL secret_key_id row as plaintext, so those keys are rejected until
+-- then.
+ALTER TABLE customer_apikey_linking ADD COLUMN IF NOT EXISTS secret_key_id VARCHAR;
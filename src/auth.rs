@@ -0,0 +1,586 @@
+use crate::database::CustomerApiKeyLinking;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use diesel::prelude::PgConnection;
+use digest::{CtOutput, Output, OutputSizeUser};
+use hmac::{Hmac, Mac};
+use log::warn;
+use secrets::{SecretCipher, SecretError};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::BTreeSet;
+use thiserror::Error;
+
+pub mod secrets;
+
+type HS = Hmac<Sha256>;
+
+/// How far a signature's `datetime` may be from the server clock by default, either way.
+pub const DEFAULT_SIGNATURE_WINDOW_SECS: i64 = 30;
+/// Overrides `DEFAULT_SIGNATURE_WINDOW_SECS`.
+pub const SIGNATURE_WINDOW_ENV: &str = "AUTH_SIGNATURE_WINDOW_SECS";
+/// Longest nonce accepted, nonces are stored in redis while their signature is valid.
+pub const MAX_NONCE_LEN: usize = 64;
+/// Largest request body read before its signature is checked, signed requests are a few hundred
+/// bytes.
+pub const MAX_BODY_BYTES: usize = 16 * 1024;
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct AuthInfo {
+    pub api_key: String,
+    pub api_secret: String,
+}
+
+/// What an api key may be used for, each private method requires one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// Reading orders, positions and account history.
+    Read,
+    /// Submitting, settling and cancelling trade orders.
+    Trade,
+    /// Submitting and settling lend orders.
+    Lend,
+}
+
+impl Scope {
+    pub fn all() -> BTreeSet<Scope> {
+        [Scope::Read, Scope::Trade, Scope::Lend].into()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Scope::Read => "read",
+            Scope::Trade => "trade",
+            Scope::Lend => "lend",
+        }
+    }
+
+    /// Scopes as stored in the `authorities` column, comma separated. Keys created before scopes
+    /// existed have none stored and keep every scope, unknown names are dropped.
+    pub fn parse_list(authorities: Option<&str>) -> BTreeSet<Scope> {
+        let Some(authorities) = authorities else {
+            return Scope::all();
+        };
+
+        authorities
+            .split(',')
+            .filter_map(|name| match name.trim() {
+                "read" => Some(Scope::Read),
+                "trade" => Some(Scope::Trade),
+                "lend" => Some(Scope::Lend),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn format_list(scopes: &BTreeSet<Scope>) -> String {
+        scopes
+            .iter()
+            .map(Scope::as_str)
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+}
+
+impl std::fmt::Display for Scope {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct UserInfo {
+    pub customer_id: i64,
+    /// Scopes of the api key the request was signed with.
+    #[serde(default)]
+    pub scopes: BTreeSet<Scope>,
+}
+
+/// Longest api key label accepted.
+pub const MAX_LABEL_LEN: usize = 64;
+
+/// An api key as `/keys` lists it, without its secret.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct ApiKeyInfo {
+    pub api_key: String,
+    pub label: String,
+    pub created_on: DateTime<Utc>,
+    pub last_used_on: Option<DateTime<Utc>>,
+    pub expires_on: Option<DateTime<Utc>>,
+    pub scopes: BTreeSet<Scope>,
+}
+
+impl From<&CustomerApiKeyLinking> for ApiKeyInfo {
+    fn from(key: &CustomerApiKeyLinking) -> Self {
+        ApiKeyInfo {
+            api_key: key.api_key.clone(),
+            label: key.label.clone(),
+            created_on: key.created_on,
+            last_used_on: key.last_used_on,
+            expires_on: key.expires_on,
+            scopes: Scope::parse_list(key.authorities.as_deref()),
+        }
+    }
+}
+
+/// A key returned by `/keys/create`, the only time its secret is shown.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreatedApiKey {
+    #[serde(flatten)]
+    pub key: ApiKeyInfo,
+    pub api_secret: String,
+}
+
+/// The signed `data` of a `/keys/create` request.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct CreateKeyArgs {
+    pub label: String,
+    #[serde(default)]
+    pub expires_on: Option<DateTime<Utc>>,
+    /// Every scope if left out.
+    #[serde(default = "Scope::all")]
+    pub scopes: BTreeSet<Scope>,
+}
+
+impl CreateKeyArgs {
+    /// Why the key can't be created, if it can't.
+    pub fn validate(&self, now: DateTime<Utc>) -> Result<(), String> {
+        if self.label.trim().is_empty() || self.label.len() > MAX_LABEL_LEN {
+            return Err(format!("Label must be 1 to {} characters", MAX_LABEL_LEN));
+        }
+        if self.expires_on.map_or(false, |expiry| expiry <= now) {
+            return Err("Expiry must be in the future".into());
+        }
+        if self.scopes.is_empty() {
+            return Err("A key needs at least one scope".into());
+        }
+        Ok(())
+    }
+}
+
+/// The signed `data` of a `/keys/revoke` request.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct RevokeKeyArgs {
+    pub api_key: String,
+}
+
+/// Fields every Keplr signed `data` of the auth service carries next to the route's own, so a
+/// captured request can't be replayed later or against another route.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SignedData {
+    /// Path of the route the signature is for, e.g. `/keys/create`.
+    pub endpoint: String,
+    /// Milliseconds since the unix epoch, like the `datetime` header of the private API.
+    pub datetime: String,
+    pub nonce: String,
+}
+
+impl SignedData {
+    /// Check the data was signed for `endpoint` within `window` of `now`. Doesn't look at the
+    /// nonce cache, see `claim_nonce`.
+    pub fn verify(
+        &self,
+        endpoint: &str,
+        now: DateTime<Utc>,
+        window: Duration,
+    ) -> Result<(), SignatureError> {
+        check_freshness(&self.datetime, &self.nonce, now, window)?;
+        if self.endpoint != endpoint {
+            return Err(SignatureError::WrongEndpoint(self.endpoint.clone()));
+        }
+
+        Ok(())
+    }
+
+    /// Record the nonce in redis, failing if `address` used it before.
+    pub fn claim_nonce(
+        &self,
+        conn: &mut redis::Connection,
+        address: &str,
+        window: Duration,
+    ) -> Result<(), SignatureError> {
+        claim_nonce(conn, address, &self.nonce, &self.datetime, window)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    #[error("Bad datetime {0:?}, expected milliseconds since the unix epoch")]
+    BadDatetime(String),
+    #[error("Signature expired")]
+    Expired,
+    #[error("Signature dated in the future")]
+    FutureDated,
+    #[error("Bad nonce, expected 1 to 64 characters")]
+    BadNonce,
+    #[error("Malformed signature")]
+    Malformed,
+    #[error("Invalid digest")]
+    InvalidDigest,
+    #[error("Nonce already used")]
+    Replayed,
+    #[error("No customer with that key")]
+    UnknownKey,
+    #[error("Signed for {0:?}")]
+    WrongEndpoint(String),
+    #[error("Redis error {0:?}")]
+    Redis(#[from] redis::RedisError),
+    #[error("Database error {0:?}")]
+    Database(diesel::result::Error),
+    #[error("Connection pool error {0:?}")]
+    R2d2(#[from] r2d2::Error),
+    #[error("Api secret error {0}")]
+    Secret(#[from] SecretError),
+    #[error("Authentication failed {0}")]
+    Internal(String),
+}
+
+impl From<diesel::result::Error> for SignatureError {
+    fn from(e: diesel::result::Error) -> Self {
+        match e {
+            diesel::result::Error::NotFound => SignatureError::UnknownKey,
+            e => SignatureError::Database(e),
+        }
+    }
+}
+
+impl SignatureError {
+    /// Status to answer a request with a signature that failed this way.
+    pub fn status(&self) -> http::StatusCode {
+        match self {
+            SignatureError::BadDatetime(_)
+            | SignatureError::BadNonce
+            | SignatureError::Malformed => http::StatusCode::BAD_REQUEST,
+            SignatureError::Expired
+            | SignatureError::FutureDated
+            | SignatureError::InvalidDigest
+            | SignatureError::Replayed
+            | SignatureError::UnknownKey
+            | SignatureError::WrongEndpoint(_) => http::StatusCode::UNAUTHORIZED,
+            SignatureError::Redis(_)
+            | SignatureError::Database(_)
+            | SignatureError::R2d2(_)
+            | SignatureError::Secret(_)
+            | SignatureError::Internal(_) => http::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+/// How far signatures may be off the server clock, from `SIGNATURE_WINDOW_ENV` if it is set.
+pub fn signature_window_from_env() -> Duration {
+    match std::env::var(SIGNATURE_WINDOW_ENV) {
+        Ok(secs) => Duration::seconds(
+            secs.parse()
+                .unwrap_or_else(|_| panic!("{} must be a number of seconds", SIGNATURE_WINDOW_ENV)),
+        ),
+        Err(_) => Duration::seconds(DEFAULT_SIGNATURE_WINDOW_SECS),
+    }
+}
+
+/// Check a signature's `datetime` is within `window` of `now` and its nonce is one the cache
+/// takes.
+fn check_freshness(
+    datetime: &str,
+    nonce: &str,
+    now: DateTime<Utc>,
+    window: Duration,
+) -> Result<(), SignatureError> {
+    let signed_at = datetime
+        .parse::<i64>()
+        .ok()
+        .and_then(|millis| Utc.timestamp_millis_opt(millis).single())
+        .ok_or_else(|| SignatureError::BadDatetime(datetime.to_string()))?;
+
+    if signed_at < now - window {
+        return Err(SignatureError::Expired);
+    }
+    if signed_at > now + window {
+        return Err(SignatureError::FutureDated);
+    }
+    if nonce.is_empty() || nonce.len() > MAX_NONCE_LEN {
+        return Err(SignatureError::BadNonce);
+    }
+
+    Ok(())
+}
+
+/// Record `nonce` of a verified signature by `signer` in redis, failing if it was used before.
+/// Nonces are kept for twice the window, after that the datetime check rejects them.
+fn claim_nonce(
+    conn: &mut redis::Connection,
+    signer: &str,
+    nonce: &str,
+    datetime: &str,
+    window: Duration,
+) -> Result<(), SignatureError> {
+    let key = format!("auth_nonce:{}:{}", signer, nonce);
+    let claimed: Option<String> = redis::cmd("SET")
+        .arg(&key)
+        .arg(datetime)
+        .arg("NX")
+        .arg("PX")
+        .arg(2 * window.num_milliseconds())
+        .query(conn)?;
+
+    match claimed {
+        Some(_) => Ok(()),
+        None => Err(SignatureError::Replayed),
+    }
+}
+
+/// A signed private api request, as the `relayer-api-key`, `signature`, `datetime` and `nonce`
+/// headers and the request body.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct SigCheck {
+    pub api_key: String,
+    pub sig: String,
+    pub datetime: String,
+    pub nonce: String,
+    pub body: String,
+}
+
+impl SigCheck {
+    /// What the client HMACs with its api secret: datetime, nonce and body joined by newlines.
+    pub fn payload(&self) -> String {
+        format!("{}\n{}\n{}", self.datetime, self.nonce, self.body)
+    }
+
+    /// Hex encoded HMAC-SHA256 of the payload.
+    pub fn sign(&self, secret: &str) -> String {
+        let mut mac = HS::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+        mac.update(self.payload().as_bytes());
+        hex::encode(mac.finalize().into_bytes())
+    }
+
+    /// Check the signature was made with `secret` within `window` of `now`. Doesn't look at the
+    /// nonce cache, see `claim_nonce`.
+    pub fn verify(
+        &self,
+        secret: &str,
+        now: DateTime<Utc>,
+        window: Duration,
+    ) -> Result<(), SignatureError> {
+        check_freshness(&self.datetime, &self.nonce, now, window)?;
+
+        let received = hex::decode(&self.sig).map_err(|_| SignatureError::Malformed)?;
+        if received.len() != HS::output_size() {
+            return Err(SignatureError::Malformed);
+        }
+        let output = Output::<HS>::clone_from_slice(&received);
+        let digest: CtOutput<HS> = CtOutput::from(&output);
+
+        let mut mac = HS::new_from_slice(secret.as_bytes()).expect("HMAC takes keys of any size");
+        mac.update(self.payload().as_bytes());
+        if mac.finalize() != digest {
+            return Err(SignatureError::InvalidDigest);
+        }
+
+        Ok(())
+    }
+
+    /// Look the active, unexpired api key up, verify the signature with its secret and claim the nonce, returning
+    /// the customer the key belongs to.
+    pub fn authenticate(
+        &self,
+        conn: &mut PgConnection,
+        redis_conn: &mut redis::Connection,
+        cipher: &SecretCipher,
+        window: Duration,
+    ) -> Result<UserInfo, SignatureError> {
+        let key = CustomerApiKeyLinking::get_key(conn, self.api_key.clone())?;
+        let secret = cipher.decrypt(
+            &key.api_key,
+            key.secret_key_id.as_deref(),
+            &key.api_salt_key,
+        )?;
+        self.verify(&secret, Utc::now(), window)?;
+        self.claim_nonce(redis_conn, window)?;
+
+        // Last use is informational, failing to record it doesn't fail the request.
+        if let Err(e) = CustomerApiKeyLinking::touch(conn, key.id) {
+            warn!("Failed to record the use of api key {}: {:?}", key.id, e);
+        }
+
+        Ok(UserInfo {
+            customer_id: key.customer_account_id,
+            scopes: Scope::parse_list(key.authorities.as_deref()),
+        })
+    }
+
+    /// Record the nonce of a verified signature in redis, failing if the api key used it
+    /// before.
+    pub fn claim_nonce(
+        &self,
+        conn: &mut redis::Connection,
+        window: Duration,
+    ) -> Result<(), SignatureError> {
+        claim_nonce(conn, &self.api_key, &self.nonce, &self.datetime, window)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &str = "secret";
+
+    fn check(datetime: DateTime<Utc>, nonce: &str) -> SigCheck {
+        let mut check = SigCheck {
+            api_key: "key".into(),
+            sig: String::new(),
+            datetime: datetime.timestamp_millis().to_string(),
+            nonce: nonce.into(),
+            body: r#"{"jsonrpc":"2.0","method":"open_orders","id":1}"#.into(),
+        };
+        check.sig = check.sign(SECRET);
+        check
+    }
+
+    #[test]
+    fn verifies_signatures_within_the_window() {
+        let now = Utc::now();
+        let window = Duration::seconds(30);
+
+        assert!(check(now, "a").verify(SECRET, now, window).is_ok());
+        assert!(check(now - Duration::seconds(29), "a")
+            .verify(SECRET, now, window)
+            .is_ok());
+        assert!(matches!(
+            check(now - Duration::seconds(31), "a").verify(SECRET, now, window),
+            Err(SignatureError::Expired)
+        ));
+        assert!(matches!(
+            check(now + Duration::seconds(31), "a").verify(SECRET, now, window),
+            Err(SignatureError::FutureDated)
+        ));
+        assert!(matches!(
+            check(now, "").verify(SECRET, now, window),
+            Err(SignatureError::BadNonce)
+        ));
+        assert!(matches!(
+            check(now, "a").verify("other", now, window),
+            Err(SignatureError::InvalidDigest)
+        ));
+
+        // The datetime and nonce are signed, changing either breaks the signature.
+        let mut moved = check(now, "a");
+        moved.datetime = (now + Duration::seconds(1)).timestamp_millis().to_string();
+        assert!(matches!(
+            moved.verify(SECRET, now, window),
+            Err(SignatureError::InvalidDigest)
+        ));
+        let mut renonced = check(now, "a");
+        renonced.nonce = "b".into();
+        assert!(matches!(
+            renonced.verify(SECRET, now, window),
+            Err(SignatureError::InvalidDigest)
+        ));
+
+        let mut garbled = check(now, "a");
+        garbled.datetime = "yesterday".into();
+        assert!(matches!(
+            garbled.verify(SECRET, now, window),
+            Err(SignatureError::BadDatetime(_))
+        ));
+    }
+
+    #[test]
+    fn binds_signed_data_to_its_endpoint_and_time() {
+        let now = Utc::now();
+        let window = Duration::seconds(30);
+        let data = |endpoint: &str, datetime: DateTime<Utc>| SignedData {
+            endpoint: endpoint.into(),
+            datetime: datetime.timestamp_millis().to_string(),
+            nonce: "a".into(),
+        };
+
+        assert!(data("/keys", now).verify("/keys", now, window).is_ok());
+        assert!(matches!(
+            data("/keys", now).verify("/keys/create", now, window),
+            Err(SignatureError::WrongEndpoint(_))
+        ));
+        assert!(matches!(
+            data("/keys", now - Duration::seconds(31)).verify("/keys", now, window),
+            Err(SignatureError::Expired)
+        ));
+
+        // The route's own fields sit next to the envelope in the same object.
+        let signed = r#"{"endpoint":"/keys/revoke","datetime":"1","nonce":"a","api_key":"k"}"#;
+        let envelope: SignedData = serde_json::from_str(signed).unwrap();
+        assert_eq!(envelope.endpoint, "/keys/revoke");
+        let args: RevokeKeyArgs = serde_json::from_str(signed).unwrap();
+        assert_eq!(args.api_key, "k");
+    }
+
+    #[test]
+    fn validates_new_key_args() {
+        let now = Utc::now();
+        let args = |label: &str, expires_on| CreateKeyArgs {
+            label: label.into(),
+            expires_on,
+            scopes: Scope::all(),
+        };
+
+        assert!(args("bot-1", None).validate(now).is_ok());
+        assert!(args("bot-1", Some(now + Duration::days(1)))
+            .validate(now)
+            .is_ok());
+        assert!(args(" ", None).validate(now).is_err());
+        assert!(args(&"x".repeat(MAX_LABEL_LEN + 1), None)
+            .validate(now)
+            .is_err());
+        assert!(args("bot-1", Some(now - Duration::days(1)))
+            .validate(now)
+            .is_err());
+
+        let mut read_only = args("dashboard", None);
+        read_only.scopes = BTreeSet::new();
+        assert!(read_only.validate(now).is_err());
+        read_only.scopes.insert(Scope::Read);
+        assert!(read_only.validate(now).is_ok());
+
+        // Scopes are optional in the signed data and default to all of them.
+        let parsed: CreateKeyArgs = serde_json::from_str(r#"{"label": "bot"}"#).unwrap();
+        assert_eq!(parsed.scopes, Scope::all());
+        let parsed: CreateKeyArgs =
+            serde_json::from_str(r#"{"label": "bot", "scopes": ["read"]}"#).unwrap();
+        assert_eq!(parsed.scopes, [Scope::Read].into());
+    }
+
+    #[test]
+    fn parses_stored_scopes() {
+        assert_eq!(Scope::parse_list(None), Scope::all());
+        assert_eq!(Scope::parse_list(Some("")), BTreeSet::new());
+        assert_eq!(
+            Scope::parse_list(Some("trade, read,admin")),
+            [Scope::Read, Scope::Trade].into()
+        );
+        let scopes = [Scope::Lend, Scope::Read].into();
+        assert_eq!(Scope::format_list(&scopes), "read,lend");
+        assert_eq!(
+            Scope::parse_list(Some(&Scope::format_list(&scopes))),
+            scopes
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn rejects_replayed_nonces() {
+        let client = redis::Client::open("redis://localhost:6379").unwrap();
+        let mut conn = client.get_connection().unwrap();
+        let window = Duration::seconds(30);
+        let nonce = uuid::Uuid::new_v4().to_string();
+
+        let first = check(Utc::now(), &nonce);
+        first.claim_nonce(&mut conn, window).unwrap();
+        assert!(matches!(
+            first.claim_nonce(&mut conn, window),
+            Err(SignatureError::Replayed)
+        ));
+
+        // Nonces are per api key.
+        let mut other_key = check(Utc::now(), &nonce);
+        other_key.api_key = "other".into();
+        other_key.claim_nonce(&mut conn, window).unwrap();
+    }
+}
@@ -0,0 +1,198 @@
+use aes_gcm::{
+    aead::{Aead, KeyInit, Payload},
+    Aes256Gcm, Nonce,
+};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+
+/// Hex encoded 32 byte server key api secrets are encrypted with.
+pub const SECRET_KEY_ENV: &str = "API_SECRET_KEY";
+/// Comma separated server keys that secrets may still be encrypted with while they are rotated to
+/// the one in `SECRET_KEY_ENV`.
+pub const PREVIOUS_SECRET_KEYS_ENV: &str = "API_SECRET_KEY_PREVIOUS";
+
+const NONCE_LEN: usize = 12;
+
+/// Server key the unit and database tests encrypt secrets with.
+#[cfg(test)]
+pub const TEST_SECRET_KEY: &str =
+    "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f";
+
+#[derive(Debug, Clone, PartialEq, Eq, Error)]
+pub enum SecretError {
+    #[error("{0} is not set")]
+    MissingKey(&'static str),
+    #[error("Server keys must be 64 hex characters")]
+    BadKey,
+    #[error("No server key with id {0}, add it to API_SECRET_KEY_PREVIOUS")]
+    UnknownKey(String),
+    #[error("Encrypted secret could not be decrypted")]
+    Corrupt,
+    #[error("Secret was never encrypted, start the auth server to encrypt it")]
+    Unencrypted,
+}
+
+#[derive(Clone)]
+struct ServerKey {
+    /// Start of the key's SHA-256, stored with every secret encrypted under it.
+    id: String,
+    cipher: Aes256Gcm,
+}
+
+impl ServerKey {
+    fn from_hex(key: &str) -> Result<ServerKey, SecretError> {
+        let bytes = hex::decode(key.trim()).map_err(|_| SecretError::BadKey)?;
+        let cipher = Aes256Gcm::new_from_slice(&bytes).map_err(|_| SecretError::BadKey)?;
+
+        Ok(ServerKey {
+            id: hex::encode(&Sha256::digest(&bytes)[..8]),
+            cipher,
+        })
+    }
+}
+
+/// Encrypts api secrets with AES-256-GCM under the current server key, and decrypts them under
+/// whichever key they were encrypted with. The api key is authenticated along with each secret so
+/// a stored secret can't be copied to another key's row.
+#[derive(Clone)]
+pub struct SecretCipher {
+    current: ServerKey,
+    previous: Vec<ServerKey>,
+}
+
+impl SecretCipher {
+    pub fn new(current: &str, previous: &[&str]) -> Result<SecretCipher, SecretError> {
+        Ok(SecretCipher {
+            current: ServerKey::from_hex(current)?,
+            previous: previous
+                .iter()
+                .map(|key| ServerKey::from_hex(key))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    /// The server key from `SECRET_KEY_ENV`, and the ones being rotated out from
+    /// `PREVIOUS_SECRET_KEYS_ENV` if it is set.
+    pub fn from_env() -> Result<SecretCipher, SecretError> {
+        let current =
+            std::env::var(SECRET_KEY_ENV).map_err(|_| SecretError::MissingKey(SECRET_KEY_ENV))?;
+        let previous = std::env::var(PREVIOUS_SECRET_KEYS_ENV).unwrap_or_default();
+        let previous: Vec<&str> = previous
+            .split(',')
+            .filter(|key| !key.trim().is_empty())
+            .collect();
+
+        SecretCipher::new(&current, &previous)
+    }
+
+    /// Id of the current server key, stored in `secret_key_id` next to every secret it encrypts.
+    pub fn key_id(&self) -> &str {
+        &self.current.id
+    }
+
+    /// The stored form of `secret`: hex of a random nonce followed by the ciphertext.
+    pub fn encrypt(&self, api_key: &str, secret: &str) -> String {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).expect("Could not get randomness");
+
+        let payload = Payload {
+            msg: secret.as_bytes(),
+            aad: api_key.as_bytes(),
+        };
+        let ciphertext = self
+            .current
+            .cipher
+            .encrypt(Nonce::from_slice(&nonce), payload)
+            .expect("AES-GCM encrypts secrets of any reasonable size");
+
+        hex::encode([&nonce[..], &ciphertext[..]].concat())
+    }
+
+    /// The plaintext of a stored secret. Rows without a key id predate encryption and are refused:
+    /// the auth server encrypts them when it starts, nothing may read them as plaintext after that.
+    pub fn decrypt(
+        &self,
+        api_key: &str,
+        key_id: Option<&str>,
+        stored: &str,
+    ) -> Result<String, SecretError> {
+        let Some(key_id) = key_id else {
+            return Err(SecretError::Unencrypted);
+        };
+        let key = std::iter::once(&self.current)
+            .chain(&self.previous)
+            .find(|key| key.id == key_id)
+            .ok_or_else(|| SecretError::UnknownKey(key_id.to_string()))?;
+
+        let stored = hex::decode(stored).map_err(|_| SecretError::Corrupt)?;
+        if stored.len() < NONCE_LEN {
+            return Err(SecretError::Corrupt);
+        }
+        let (nonce, ciphertext) = stored.split_at(NONCE_LEN);
+        let payload = Payload {
+            msg: ciphertext,
+            aad: api_key.as_bytes(),
+        };
+        let secret = key
+            .cipher
+            .decrypt(Nonce::from_slice(nonce), payload)
+            .map_err(|_| SecretError::Corrupt)?;
+
+        String::from_utf8(secret).map_err(|_| SecretError::Corrupt)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NEW_KEY: &str = "f0e0d0c0b0a090807060504030201000f0e0d0c0b0a090807060504030201000";
+
+    #[test]
+    fn encrypts_secrets_under_the_current_key() {
+        let cipher = SecretCipher::new(TEST_SECRET_KEY, &[]).unwrap();
+        let stored = cipher.encrypt("key-1", "secret");
+        assert!(!stored.contains(&hex::encode("secret")));
+        assert_ne!(stored, cipher.encrypt("key-1", "secret"));
+
+        let key_id = Some(cipher.key_id());
+        assert_eq!(cipher.decrypt("key-1", key_id, &stored).unwrap(), "secret");
+        // Bound to the api key it was stored for.
+        assert_eq!(
+            cipher.decrypt("key-2", key_id, &stored),
+            Err(SecretError::Corrupt)
+        );
+        assert_eq!(
+            cipher.decrypt("key-1", key_id, &stored[2..]),
+            Err(SecretError::Corrupt)
+        );
+        // Rows from before encryption are never read as plaintext.
+        assert_eq!(
+            cipher.decrypt("key-1", None, "secret"),
+            Err(SecretError::Unencrypted)
+        );
+    }
+
+    #[test]
+    fn decrypts_secrets_under_previous_keys() {
+        let old = SecretCipher::new(TEST_SECRET_KEY, &[]).unwrap();
+        let stored = old.encrypt("key-1", "secret");
+
+        let rotated = SecretCipher::new(NEW_KEY, &[TEST_SECRET_KEY]).unwrap();
+        assert_ne!(rotated.key_id(), old.key_id());
+        assert_eq!(
+            rotated
+                .decrypt("key-1", Some(old.key_id()), &stored)
+                .unwrap(),
+            "secret"
+        );
+
+        let new_only = SecretCipher::new(NEW_KEY, &[]).unwrap();
+        assert_eq!(
+            new_only.decrypt("key-1", Some(old.key_id()), &stored),
+            Err(SecretError::UnknownKey(old.key_id().to_string()))
+        );
+        assert!(SecretCipher::new("not hex", &[]).is_err());
+        assert!(SecretCipher::new(&TEST_SECRET_KEY[2..], &[]).is_err());
+    }
+}
@@ -1,12 +1,25 @@
-use jsonrpsee::server::ServerBuilder;
-use log::info;
-use relayerarchiverlib::{rpc, ws};
+use jsonrpsee::server::{ServerBuilder, ServerHandle};
+use log::{error, info};
+use relayerarchiverlib::{
+    auth::{secrets::SecretCipher, signature_window_from_env},
+    metrics,
+    metrics::RpcMetrics,
+    rpc,
+    rpc::{auth::ApiKeyAuth, health::HealthCheck, shutdown::Shutdown, RelayerContext},
+    ws,
+};
 use std::{net::SocketAddr, time::Duration};
 use structopt::StructOpt;
-use tokio::time::sleep;
+use tokio::{
+    signal::unix::{signal, SignalKind},
+    time::sleep,
+};
 use tower::ServiceBuilder;
 use tower_http::cors::{Any, CorsLayer};
 
+/// Time given to websocket connections to flush the final subscription notifications.
+const SUBSCRIPTION_GRACE: Duration = Duration::from_millis(500);
+
 #[derive(Debug, StructOpt)]
 #[structopt(name = "Relayer API", about = "Twilight Relayer API server")]
 struct Opt {
@@ -31,9 +44,49 @@ struct Opt {
         help = "Websocket address the server will listen on"
     )]
     ws_listen_addr: SocketAddr,
+    #[structopt(
+        short = "-a",
+        long = "--admin-rpc",
+        default_value("127.0.0.1:8991"),
+        help = "Endpoint for the admin API, keep this off public interfaces."
+    )]
+    admin_rpc: SocketAddr,
+    #[structopt(
+        short = "-m",
+        long = "--metrics",
+        default_value("0.0.0.0:9100"),
+        help = "Endpoint serving prometheus metrics on /metrics."
+    )]
+    metrics: SocketAddr,
+    #[structopt(
+        long,
+        default_value("60"),
+        help = "Seconds without a new btc_usd_price row before /health reports degraded."
+    )]
+    max_price_age: u64,
+    #[structopt(
+        long,
+        default_value("30"),
+        help = "Seconds to wait for in-flight calls to finish on shutdown."
+    )]
+    shutdown_timeout: u64,
+    #[structopt(
+        long,
+        help = "Verify api key signatures on the private API instead of relying on the nginx sidecar."
+    )]
+    verify_api_keys: bool,
 }
-#[tokio::main(flavor = "multi_thread", worker_threads = 40)]
-// #[tokio::main]
+
+/// Resolves on the first SIGINT or SIGTERM.
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).expect("Failed to install SIGTERM handler");
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("SIGINT received"),
+        _ = terminate.recv() => info!("SIGTERM received"),
+    }
+}
+#[tokio::main]
 async fn main() {
     let opts = Opt::from_args();
     dotenv::dotenv().expect("dotenv file not found!");
@@ -54,56 +107,120 @@ async fn main() {
         .allow_origin(Any);
     // .allow_headers([hyper::header::CONTENT_TYPE]);
 
+    let shutdown = Shutdown::default();
+
+    let metrics_addr = opts.metrics;
+    tokio::spawn(async move {
+        if let Err(e) = metrics::serve(metrics_addr).await {
+            error!("Metrics server failed: {:?}", e);
+        }
+    });
+
     // TODO: env var
     let middleware = ServiceBuilder::new()
         .layer(cors)
-        .layer(relayerarchiverlib::rpc::headers::HeaderExtractLayer);
+        .layer(relayerarchiverlib::rpc::headers::HeaderExtractLayer)
+        .layer(shutdown.layer());
     let ping_interval = Duration::from_secs(300);
 
     info!("Starting public RPC server on {:?}", opts.public_rpc);
     let addrs: &[SocketAddr] = &[opts.public_rpc];
+    // The websocket watcher builds the open candles, candle_data serves the same bars.
+    let candles = ws::LiveCandles::default();
+    let ctx = RelayerContext::new(&database_url, &redis_url).with_live_candles(candles.clone());
+    let health = HealthCheck::new(ctx.clone(), Duration::from_secs(opts.max_price_age));
+    let methods = rpc::init_public_methods(ctx);
     let public_server = ServerBuilder::new()
         .ping_interval(ping_interval)
-        .set_middleware(middleware.clone())
+        .set_middleware(middleware.clone().layer(health.layer()))
+        .set_logger(RpcMetrics::new("public", methods.method_names()))
         .build(addrs)
         .await
         .expect("Failed to build public API server");
 
-    let methods = rpc::init_public_methods(&database_url, &redis_url);
-    let _pub_handle = public_server
+    let pub_handle = public_server
         .start(methods)
         .expect("Failed to start API server");
 
     info!("Starting private RPC server on {:?}", opts.private_rpc);
     let addrs: &[SocketAddr] = &[opts.private_rpc];
+    let ctx = RelayerContext::new(&database_url, &redis_url);
+    // Without the flag the sidecar has already checked the signature and injected the user.
+    let auth = opts.verify_api_keys.then(|| {
+        let cipher = SecretCipher::from_env().expect("Could not load the api secret key");
+        ApiKeyAuth::new(&ctx, signature_window_from_env(), cipher).layer()
+    });
+    if auth.is_some() {
+        info!("Verifying api key signatures on the private API");
+    }
+    let methods = rpc::init_private_methods(ctx);
     let private_server = ServerBuilder::new()
         .ping_interval(ping_interval)
-        .set_middleware(middleware.clone())
+        .set_middleware(middleware.clone().option_layer(auth))
+        .set_logger(RpcMetrics::new("private", methods.method_names()))
         .build(addrs)
         .await
         .expect("Failed to build private API server");
 
-    let methods = rpc::init_private_methods(&database_url, &redis_url);
-    let _priv_handle = private_server
+    let priv_handle = private_server
+        .start(methods)
+        .expect("Failed to start API server");
+
+    info!("Starting admin RPC server on {:?}", opts.admin_rpc);
+    let addrs: &[SocketAddr] = &[opts.admin_rpc];
+    let methods = rpc::init_admin_methods(RelayerContext::new(&database_url, &redis_url));
+    // No CORS layer, browsers must not be able to call the admin API from another origin.
+    let admin_middleware = ServiceBuilder::new()
+        .layer(relayerarchiverlib::rpc::headers::HeaderExtractLayer)
+        .layer(shutdown.layer());
+    let admin_server = ServerBuilder::new()
+        .ping_interval(ping_interval)
+        .set_middleware(admin_middleware)
+        .set_logger(RpcMetrics::new("admin", methods.method_names()))
+        .build(addrs)
+        .await
+        .expect("Failed to build admin API server");
+
+    let admin_handle = admin_server
         .start(methods)
         .expect("Failed to start API server");
 
     let ws_addrs: &[SocketAddr] = &[opts.ws_listen_addr];
     info!("Starting WS server on {:?}", opts.ws_listen_addr);
     let ws_server = ServerBuilder::new()
+        .set_middleware(ServiceBuilder::new().layer(shutdown.layer()))
         .build(ws_addrs)
         .await
         .expect("Failed to build websocket server");
 
-    let ws_methods = ws::init_methods(&database_url, &redis_url);
-    let _ws_handle = ws_server
+    let ws_methods = ws::init_methods(&database_url, &redis_url, &shutdown, candles);
+    let ws_handle = ws_server
         .start(ws_methods)
         .expect("Failed to start websocket server");
 
-    //TODO: need an exit handler.
-    loop {
-        sleep(Duration::from_secs(5)).await;
-    }
+    shutdown_signal().await;
+
+    // Refuse new calls and send subscribers their final notification, then give in-flight calls
+    // a chance to finish before the servers drop their connections.
+    info!("Shutting down, draining in-flight calls");
+    shutdown.close();
+    tokio::join!(
+        shutdown.drained(Duration::from_secs(opts.shutdown_timeout)),
+        sleep(SUBSCRIPTION_GRACE),
+    );
 
-    //handle.stop().expect("Oopsie!");
+    let handles: [(&str, ServerHandle); 4] = [
+        ("public", pub_handle),
+        ("private", priv_handle),
+        ("admin", admin_handle),
+        ("websocket", ws_handle),
+    ];
+    for (name, handle) in handles {
+        if let Err(e) = handle.stop() {
+            error!("Failed to stop {} server: {:?}", name, e);
+            continue;
+        }
+        handle.stopped().await;
+        info!("Stopped {} server", name);
+    }
 }
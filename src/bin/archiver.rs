@@ -1,6 +1,11 @@
 use crossbeam_channel::unbounded;
-use relayerarchiverlib::kafka;
+use log::info;
+use relayerarchiverlib::event_source::EventSource;
+use relayerarchiverlib::kafka::KafkaSource;
+use relayerarchiverlib::metrics;
 use relayerarchiverlib::DatabaseArchiver;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::sync::{atomic::AtomicBool, Arc};
 
 // const SNAPSHOT_TOPIC: &str = "CoreEventLogTopic";
 // const ARCHIVER_GROUP: &str = "Archiver_Redis";
@@ -22,12 +27,50 @@ fn main() {
     let snapshot_topic = std::env::var("CORE_EVENT_LOG").unwrap_or("CoreEventLogTopic".to_string());
     let archiver_group =
         std::env::var("ARCHIVER_KAFKA_GROUP").unwrap_or("Archiver_Redis".to_string());
-    let (tx, rx) = unbounded();
-    let (completions, _handle) = kafka::start_consumer(archiver_group, snapshot_topic, tx);
+    let metrics_addr = std::env::var("ARCHIVER_METRICS").unwrap_or("0.0.0.0:9101".to_string());
+    let rebuild_cache = std::env::var("ARCHIVER_REBUILD_CACHE")
+        .map(|v| v == "1" || v.eq_ignore_ascii_case("true"))
+        .unwrap_or(false);
+    let _metrics = metrics::serve_in_background(
+        metrics_addr
+            .parse()
+            .expect("ARCHIVER_METRICS is not a valid socket address"),
+    );
+
+    // The first signal lets the archiver flush and commit its offsets, a second one exits
+    // immediately.
+    let shutdown = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, shutdown.clone())
+            .expect("Failed to install signal handler");
+        signal_hook::flag::register(signal, shutdown.clone())
+            .expect("Failed to install signal handler");
+    }
+
+    let database_worker =
+        DatabaseArchiver::from_host(&database_url, &redis_url, &archiver_group, &snapshot_topic)
+            .with_shutdown(shutdown.clone());
+    if rebuild_cache {
+        info!("Rebuilding the redis cache from postgres");
+        database_worker
+            .rebuild_cache()
+            .expect("Failed to rebuild the redis cache");
+    }
 
-    let database_worker = DatabaseArchiver::from_host(&database_url, &redis_url, completions);
+    let (tx, rx) = unbounded();
+    let (dead_letter_tx, dead_letter_rx) = unbounded();
+    let source = KafkaSource::from_env(archiver_group, snapshot_topic)
+        .with_start_offsets(database_worker.applied_offsets())
+        .with_dead_letters(dead_letter_tx)
+        .with_shutdown(shutdown);
+    let (completions, handle) = source.start(tx);
 
     database_worker
-        .run(rx)
+        .run(rx, completions, dead_letter_rx)
         .expect("Archiver loop quit unexpectedly!");
+
+    info!("Waiting for the kafka consumer to commit offsets");
+    if handle.join().is_err() {
+        eprintln!("Kafka consumer thread panicked");
+    }
 }
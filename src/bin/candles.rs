@@ -0,0 +1,79 @@
+use chrono::prelude::*;
+use diesel::prelude::*;
+use log::info;
+use relayerarchiverlib::database::BtcUsdPrice;
+use relayerarchiverlib::rpc::Interval;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "Relayer candles",
+    about = "Rebuild the materialized candles from the price and trade history"
+)]
+struct Opt {
+    #[structopt(long, help = "Rebuild candles from this RFC 3339 timestamp.")]
+    since: DateTime<Utc>,
+    #[structopt(
+        long,
+        help = "Rebuild candles up to this RFC 3339 timestamp, defaults to now."
+    )]
+    until: Option<DateTime<Utc>>,
+    #[structopt(
+        long,
+        parse(try_from_str = parse_interval),
+        help = "Only rebuild this interval (e.g. FIVE_MINUTE), may be repeated. Defaults to all. \
+                Intervals are rolled up from the 1 minute or 1 hour candles, which must be current."
+    )]
+    interval: Vec<Interval>,
+    #[structopt(
+        long,
+        default_value = "24",
+        help = "Hours of history rebuilt per statement."
+    )]
+    chunk_hours: i64,
+}
+
+fn parse_interval(s: &str) -> Result<Interval, serde_json::Error> {
+    serde_json::from_value(serde_json::Value::String(s.to_string()))
+}
+
+fn main() {
+    if let Err(_) = dotenv::dotenv() {
+        eprintln!("DOTENV file not found");
+    }
+
+    tracing_subscriber::fmt::Subscriber::builder()
+        .with_env_filter(tracing_subscriber::filter::EnvFilter::from_default_env())
+        .with_level(true)
+        .with_line_number(true)
+        .init();
+
+    let opts = Opt::from_args();
+    let until = opts.until.unwrap_or_else(Utc::now);
+    // Rebuilt in the order of `MATERIALIZED`, so every rollup reads freshly rebuilt candles.
+    let intervals: Vec<Interval> = Interval::MATERIALIZED
+        .into_iter()
+        .filter(|interval| opts.interval.is_empty() || opts.interval.contains(interval))
+        .collect();
+    let chunk = chrono::Duration::hours(opts.chunk_hours.max(1));
+
+    let database_url = std::env::var("DATABASE_URL").expect("No database url found!");
+    let mut conn = PgConnection::establish(&database_url).expect("Could not connect to database");
+
+    for interval in intervals {
+        // Each chunk is its own statement, so a long backfill doesn't hold one huge transaction
+        // open against the archiver's writes.
+        let mut since = opts.since;
+        while since < until {
+            let end = (since + chunk).min(until);
+            BtcUsdPrice::backfill_candles(&mut conn, interval, since, end)
+                .expect("Candle backfill failed");
+            since = end;
+        }
+
+        info!(
+            "Rebuilt {:?} candles from {} to {}",
+            interval, opts.since, until
+        );
+    }
+}
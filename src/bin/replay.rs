@@ -0,0 +1,262 @@
+use chrono::prelude::*;
+use log::{error, info};
+use relayerarchiverlib::error::ApiError;
+use relayerarchiverlib::event_source::{event_name, event_timestamp, FileSource};
+use relayerarchiverlib::DatabaseArchiver;
+use std::{
+    collections::BTreeMap,
+    io::{self, BufRead, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+use structopt::StructOpt;
+use thiserror::Error;
+
+const REPLAY_GROUP: &str = "Replay";
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "Relayer replay",
+    about = "Rebuild the archive from a JSONL dump of the core event log"
+)]
+struct Opt {
+    #[structopt(parse(from_os_str), help = "JSONL event log to replay.")]
+    input: PathBuf,
+    #[structopt(long, help = "Skip events before this RFC 3339 timestamp.")]
+    from: Option<DateTime<Utc>>,
+    #[structopt(long, help = "Skip events after this RFC 3339 timestamp.")]
+    to: Option<DateTime<Utc>>,
+    #[structopt(long, help = "Only count events per type, don't touch the database.")]
+    dry_run: bool,
+    #[structopt(long, help = "Don't ask before writing to the database.")]
+    yes: bool,
+    #[structopt(
+        long,
+        help = "Also reconcile the redis cache and order book in ORDERBOOK_REDIS and apply the \
+                replayed events to the book, like the archiver does."
+    )]
+    redis: bool,
+    #[structopt(
+        long,
+        help = "Succeed even if some lines of the event log could not be decoded."
+    )]
+    allow_undecodable: bool,
+}
+
+#[derive(Debug, Error)]
+enum ReplayError {
+    #[error("Failed to read the event log: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Archive(#[from] ApiError),
+    #[error("{0} is not set")]
+    MissingEnv(&'static str),
+    #[error("Replay aborted")]
+    Aborted,
+    #[error("{0} lines of the event log could not be decoded, pass --allow-undecodable to accept")]
+    Undecodable(u64),
+}
+
+impl Opt {
+    /// Events without a timestamp of their own take the last one seen in the log, and are only
+    /// replayed before the first timestamp when there's no lower bound.
+    fn in_range(&self, ts: Option<DateTime<Utc>>) -> bool {
+        match ts {
+            Some(ts) => {
+                self.from.map_or(true, |from| ts >= from) && self.to.map_or(true, |to| ts <= to)
+            }
+            None => self.from.is_none(),
+        }
+    }
+}
+
+/// Ask on the terminal before the replay writes to `database_url`, and `redis_url` if given.
+fn confirm(database_url: &str, redis_url: Option<&str>) -> Result<(), ReplayError> {
+    match redis_url {
+        Some(redis_url) => print!(
+            "Replaying into postgres {} and redis {}, continue? [y/N] ",
+            database_url, redis_url
+        ),
+        None => print!("Replaying into postgres {}, continue? [y/N] ", database_url),
+    }
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().lock().read_line(&mut answer)?;
+    match answer.trim() {
+        "y" | "Y" | "yes" => Ok(()),
+        _ => Err(ReplayError::Aborted),
+    }
+}
+
+fn replay(opts: &Opt) -> Result<(), ReplayError> {
+    let mut archiver = if opts.dry_run {
+        None
+    } else {
+        let database_url =
+            std::env::var("DATABASE_URL").map_err(|_| ReplayError::MissingEnv("DATABASE_URL"))?;
+        let redis_url = if opts.redis {
+            let url = std::env::var("ORDERBOOK_REDIS")
+                .map_err(|_| ReplayError::MissingEnv("ORDERBOOK_REDIS"))?;
+            Some(url)
+        } else {
+            None
+        };
+        if !opts.yes {
+            confirm(&database_url, redis_url.as_deref())?;
+        }
+        let topic = opts.input.display().to_string();
+
+        Some(match redis_url {
+            Some(redis_url) => {
+                DatabaseArchiver::from_host(&database_url, &redis_url, REPLAY_GROUP, &topic)
+            }
+            None => DatabaseArchiver::postgres_only(&database_url, REPLAY_GROUP, &topic),
+        })
+    };
+
+    let mut counts: BTreeMap<&'static str, u64> = BTreeMap::new();
+    let mut skipped = 0u64;
+    let mut last_seen = None;
+    let mut failure = None;
+
+    let undecodable = FileSource::new(&opts.input).for_each(|_, event| {
+        last_seen = event_timestamp(&event).or(last_seen);
+
+        if !opts.in_range(last_seen) {
+            skipped += 1;
+            return true;
+        }
+
+        *counts.entry(event_name(&event)).or_default() += 1;
+
+        if let Some(archiver) = archiver.as_mut() {
+            if let Err(e) = archiver.apply(event) {
+                failure = Some(e);
+                return false;
+            }
+        }
+
+        true
+    })?;
+
+    if let Some(e) = failure {
+        return Err(e.into());
+    }
+
+    if let Some(archiver) = archiver.as_mut() {
+        archiver.flush()?;
+    }
+
+    let total: u64 = counts.values().sum();
+    for (name, count) in counts.iter() {
+        println!("{:<28} {}", name, count);
+    }
+    println!("{:<28} {}", "total", total);
+    println!("{:<28} {}", "skipped", skipped);
+    println!("{:<28} {}", "undecodable", undecodable);
+
+    info!(
+        "Replayed {} events from {:?}{}",
+        total,
+        opts.input,
+        if opts.dry_run { " (dry run)" } else { "" }
+    );
+
+    if undecodable > 0 && !opts.allow_undecodable {
+        return Err(ReplayError::Undecodable(undecodable));
+    }
+
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    if let Err(_) = dotenv::dotenv() {
+        eprintln!("DOTENV file not found");
+    }
+
+    tracing_subscriber::fmt::Subscriber::builder()
+        .with_env_filter(tracing_subscriber::filter::EnvFilter::from_default_env())
+        .with_level(true)
+        .with_line_number(true)
+        .init();
+
+    let opts = Opt::from_args();
+
+    match replay(&opts) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("Replay failed: {}", e);
+            eprintln!("Replay failed: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(args: &[&str]) -> Opt {
+        Opt::from_iter(std::iter::once("replay").chain(args.iter().copied()))
+    }
+
+    fn at(ts: &str) -> Option<DateTime<Utc>> {
+        Some(ts.parse().unwrap())
+    }
+
+    #[test]
+    fn in_range_is_inclusive_on_both_ends() {
+        let opts = opts(&[
+            "events.jsonl",
+            "--from",
+            "2026-03-01T00:00:00Z",
+            "--to",
+            "2026-03-02T00:00:00Z",
+        ]);
+
+        assert!(!opts.in_range(at("2026-02-28T23:59:59Z")));
+        assert!(opts.in_range(at("2026-03-01T00:00:00Z")));
+        assert!(opts.in_range(at("2026-03-01T12:00:00Z")));
+        assert!(opts.in_range(at("2026-03-02T00:00:00Z")));
+        assert!(!opts.in_range(at("2026-03-02T00:00:01Z")));
+    }
+
+    #[test]
+    fn in_range_handles_open_bounds() {
+        let from = opts(&["events.jsonl", "--from", "2026-03-01T00:00:00Z"]);
+        assert!(!from.in_range(at("2026-02-01T00:00:00Z")));
+        assert!(from.in_range(at("2027-01-01T00:00:00Z")));
+
+        let to = opts(&["events.jsonl", "--to", "2026-03-01T00:00:00Z"]);
+        assert!(to.in_range(at("2020-01-01T00:00:00Z")));
+        assert!(!to.in_range(at("2026-03-01T00:00:01Z")));
+
+        let all = opts(&["events.jsonl"]);
+        assert!(all.in_range(at("2026-03-01T00:00:00Z")));
+        assert!(all.in_range(None));
+    }
+
+    #[test]
+    fn undecodable_lines_fail_the_replay() {
+        let path = std::env::temp_dir().join(format!("events-{}.jsonl", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "not an event\n").unwrap();
+        let input = path.to_str().unwrap();
+
+        let strict = opts(&[input, "--dry-run"]);
+        assert!(matches!(replay(&strict), Err(ReplayError::Undecodable(1))));
+        let lenient = opts(&[input, "--dry-run", "--allow-undecodable"]);
+        assert!(replay(&lenient).is_ok());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn untimed_events_only_pass_without_a_lower_bound() {
+        let from = opts(&["events.jsonl", "--from", "2026-03-01T00:00:00Z"]);
+        assert!(!from.in_range(None));
+
+        let to = opts(&["events.jsonl", "--to", "2026-03-01T00:00:00Z"]);
+        assert!(to.in_range(None));
+    }
+}
@@ -0,0 +1,114 @@
+-- order book script, version 2. Bump SCRIPT_VERSION in book_script.rs with every change.
+-- args: <order_id> <order_status> <side> <price> <price_cents> <position_size> <rfc3339> <timestamp_millis> <exp_time> <execution_type>
+--
+-- Each side of the book is a hash <side>:sizes of price in cents -> total size at that price, and
+-- a sorted set <side>:prices of the prices with a size, scored by price for ordered reads.
+-- <orders> maps every resting order to its price in cents.
+local id = ARGV[1]
+local status = ARGV[2]
+local side = ARGV[3]
+local price = tonumber(ARGV[4])
+local price_cents = tonumber(ARGV[5])
+local size = tonumber(ARGV[6])
+local timestamp = ARGV[7]
+local time = tonumber(ARGV[8])
+local exp_time = tonumber(ARGV[9])
+-- OPEN_LIMIT or CLOSE_LIMIT or OPEN_MARKET or CLOSE_MARKET
+local execution_type = ARGV[10]
+redis.call('ECHO', 'id: ' .. id)
+
+local function level_size(price_cents)
+    if not price_cents then
+        return 0
+    end
+    return tonumber(redis.call('HGET', side .. ':sizes', price_cents)) or 0
+end
+
+local function set_level(price_cents, size)
+    if size > 0 then
+        redis.call('HSET', side .. ':sizes', price_cents, size)
+        redis.call('ZADD', side .. ':prices', price_cents, price_cents)
+    else
+        redis.call('HDEL', side .. ':sizes', price_cents)
+        redis.call('ZREM', side .. ':prices', price_cents)
+    end
+end
+
+if (status == "FILLED" or status == "SETTLED" or status == "LIQUIDATE") and execution_type ~= "CLOSE_LIMIT" then
+    local old_price = tonumber(redis.call('HGET', 'orders', id))
+    redis.call('HDEL', 'orders', id)
+
+
+    local table = { order_id = id, side = side, price = price, positionsize = size, timestamp = timestamp }
+
+    local order_json = cjson.encode(table)
+    redis.call('ZADD', 'recent_orders', time, order_json)
+
+
+    local result = level_size(old_price)
+    if result == 0 then
+        return
+    end
+    local new_size = 0
+    if (status == "SETTLED" or status == "LIQUIDATE") then
+        new_size = result - size
+    else
+        new_size = result - (size*old_price/price_cents)
+    end
+
+    set_level(old_price, new_size)
+    return
+end
+
+-- settle order on limit
+-- just opened a new order
+if status == "PENDING" or (status == "FILLED" and execution_type == "CLOSE_LIMIT") then
+    -- if the limit order is already exist then remove the old limit price and position size
+    local is_exist =redis.call('HEXISTS', 'orders', id)
+    if is_exist == 1 then
+        local old_price = tonumber(redis.call('HGET', 'orders', id))
+        redis.call('HDEL', 'orders', id)
+        local old_position_size = level_size(old_price)
+        if old_position_size > 0 then
+            local new_size = 0
+            if status == "PENDING" then
+                new_size = old_position_size - (size*old_price/price_cents)
+            else
+                new_size = old_position_size - size
+            end
+
+            set_level(old_price, new_size)
+        end
+    end
+    -- add the new limit price and position size
+    redis.call('HSET', 'orders', id, price_cents)
+
+    set_level(price_cents, level_size(price_cents) + size)
+end
+
+if status == "REMOVE_SORTED_SET" then
+    local old_price = tonumber(redis.call('HGET', 'orders', id))
+    if not old_price then return end
+    redis.call('HDEL', 'orders', id)
+    local old_position_size = level_size(old_price)
+    if old_position_size == 0 then
+        return
+    end
+    set_level(old_price, old_position_size - size)
+    return
+end
+
+-- if order gets canncelled
+if status == "CANCELLED" then
+    local old_price = tonumber(redis.call('HGET', 'orders', id))
+    redis.call('HDEL', 'orders', id)
+    local old_position_size = level_size(old_price)
+    if old_position_size == 0 then
+        return
+    end
+    set_level(old_price, old_position_size - size)
+    return
+end
+
+-- TODO: clean out <recent_orders> expired > 24h...
+redis.call('ZREMRANGEBYSCORE', 'recent_orders', 0, exp_time)
@@ -0,0 +1,283 @@
+use crate::order_book::BookSide;
+use std::collections::BTreeMap;
+
+/// The lua script the archiver runs for every order event to keep the redis order book
+/// (`bid:sizes`, `bid:prices`, `ask:sizes`, `ask:prices`, `orders`) and `recent_orders` up to
+/// date.
+pub const UPDATE_FN: &str = include_str!("book_script.lua");
+
+/// Version of `UPDATE_FN`, stored in redis under `SCRIPT_VERSION_KEY` when the archiver loads it.
+pub const SCRIPT_VERSION: u32 = 2;
+pub const SCRIPT_VERSION_KEY: &str = "order_book_script_version";
+
+/// The arguments of a script call that affect the order book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptCall {
+    pub id: String,
+    /// An `OrderStatus`, or `REMOVE_SORTED_SET` to take a close limit order off the book.
+    pub status: String,
+    pub side: BookSide,
+    pub price_cents: i64,
+    pub size: i64,
+    /// `OPEN_LIMIT`, `OPEN_MARKET`, `CLOSE_LIMIT` or `CLOSE_MARKET`.
+    pub execution_type: String,
+}
+
+/// A pure rust model of `UPDATE_FN`, applying script calls to the same keys with the same
+/// semantics. A side is the `<side>:sizes` hash, its `<side>:prices` sorted set holds the same
+/// prices so it isn't modelled separately.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ScriptBook {
+    pub orders: BTreeMap<String, i64>,
+    bid: BTreeMap<i64, f64>,
+    ask: BTreeMap<i64, f64>,
+}
+
+impl ScriptBook {
+    /// `(price_cents, size)` of every level of a side, lowest price first.
+    pub fn levels(&self, side: BookSide) -> Vec<(i64, f64)> {
+        self.side(side)
+            .iter()
+            .map(|(price, size)| (*price, *size))
+            .collect()
+    }
+
+    pub fn apply(&mut self, call: &ScriptCall) {
+        let status = call.status.as_str();
+        let execution_type = call.execution_type.as_str();
+        let size = call.size as f64;
+
+        if matches!(status, "FILLED" | "SETTLED" | "LIQUIDATE") && execution_type != "CLOSE_LIMIT" {
+            let old_price = self.orders.remove(&call.id);
+
+            let result = self.level_size(call.side, old_price);
+            // A zero result also covers an order that was never on the book.
+            let Some(old_price) = old_price.filter(|_| result != 0.0) else {
+                return;
+            };
+            let new_size = if status == "FILLED" {
+                result - (size * old_price as f64 / call.price_cents as f64)
+            } else {
+                result - size
+            };
+
+            self.set_level(call.side, old_price, new_size);
+            return;
+        }
+
+        if status == "PENDING" || (status == "FILLED" && execution_type == "CLOSE_LIMIT") {
+            if let Some(old_price) = self.orders.remove(&call.id) {
+                let old_size = self.level_size(call.side, Some(old_price));
+                if old_size > 0.0 {
+                    let new_size = if status == "PENDING" {
+                        old_size - (size * old_price as f64 / call.price_cents as f64)
+                    } else {
+                        old_size - size
+                    };
+
+                    self.set_level(call.side, old_price, new_size);
+                }
+            }
+            self.orders.insert(call.id.clone(), call.price_cents);
+
+            let result = self.level_size(call.side, Some(call.price_cents));
+            self.set_level(call.side, call.price_cents, result + size);
+        }
+
+        if status == "REMOVE_SORTED_SET" || status == "CANCELLED" {
+            let old_price = self.orders.remove(&call.id);
+
+            let old_size = self.level_size(call.side, old_price);
+            let Some(old_price) = old_price.filter(|_| old_size != 0.0) else {
+                return;
+            };
+
+            self.set_level(call.side, old_price, old_size - size);
+        }
+    }
+
+    /// `level_size` of the script, 0 for a missing price or level.
+    fn level_size(&self, side: BookSide, price_cents: Option<i64>) -> f64 {
+        price_cents
+            .and_then(|price| self.side(side).get(&price).copied())
+            .unwrap_or(0.0)
+    }
+
+    /// `set_level` of the script, a size of 0 or less removes the level.
+    fn set_level(&mut self, side: BookSide, price_cents: i64, size: f64) {
+        let levels = match side {
+            BookSide::Bid => &mut self.bid,
+            BookSide::Ask => &mut self.ask,
+        };
+        if size > 0.0 {
+            levels.insert(price_cents, size);
+        } else {
+            levels.remove(&price_cents);
+        }
+    }
+
+    fn side(&self, side: BookSide) -> &BTreeMap<i64, f64> {
+        match side {
+            BookSide::Bid => &self.bid,
+            BookSide::Ask => &self.ask,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn call(id: &str, status: &str, side: BookSide, price_cents: i64, size: i64) -> ScriptCall {
+        ScriptCall {
+            id: id.into(),
+            status: status.into(),
+            side,
+            price_cents,
+            size,
+            execution_type: "OPEN_LIMIT".into(),
+        }
+    }
+
+    #[test]
+    fn script_carries_its_version() {
+        let header = UPDATE_FN.lines().next().unwrap();
+        assert!(header.contains(&format!("version {}.", SCRIPT_VERSION)));
+    }
+
+    #[test]
+    fn model_follows_the_order_lifecycle() {
+        let mut book = ScriptBook::default();
+        book.apply(&call("a", "PENDING", BookSide::Bid, 4_200_000, 100));
+        book.apply(&call("b", "PENDING", BookSide::Bid, 4_200_000, 50));
+        book.apply(&call("c", "PENDING", BookSide::Bid, 4_100_000, 30));
+        assert_eq!(
+            book.levels(BookSide::Bid),
+            vec![(4_100_000, 30.0), (4_200_000, 150.0)]
+        );
+
+        book.apply(&call("a", "FILLED", BookSide::Bid, 4_200_000, 100));
+        book.apply(&call("c", "CANCELLED", BookSide::Bid, 4_100_000, 30));
+        assert_eq!(book.levels(BookSide::Bid), vec![(4_200_000, 50.0)]);
+        assert_eq!(book.orders.keys().collect::<Vec<_>>(), vec!["b"]);
+
+        // Levels of equal size are kept apart.
+        book.apply(&call("d", "PENDING", BookSide::Bid, 4_000_000, 50));
+        assert_eq!(
+            book.levels(BookSide::Bid),
+            vec![(4_000_000, 50.0), (4_200_000, 50.0)]
+        );
+    }
+
+    fn script_call() -> impl Strategy<Value = ScriptCall> {
+        let status = prop::sample::select(vec![
+            "PENDING",
+            "FILLED",
+            "SETTLED",
+            "LIQUIDATE",
+            "CANCELLED",
+            "REMOVE_SORTED_SET",
+        ]);
+        let execution_type = prop::sample::select(vec![
+            "OPEN_LIMIT",
+            "OPEN_MARKET",
+            "CLOSE_LIMIT",
+            "CLOSE_MARKET",
+        ]);
+        let side = prop::sample::select(vec![BookSide::Bid, BookSide::Ask]);
+        // Few ids, prices and sizes, so calls keep hitting the same orders and levels.
+        (
+            0..4u8,
+            status,
+            side,
+            prop::sample::select(vec![4_200_000i64, 4_250_000, 4_300_000]),
+            prop::sample::select(vec![10i64, 20, 30, 45]),
+            execution_type,
+        )
+            .prop_map(
+                |(id, status, side, price_cents, size, execution_type)| ScriptCall {
+                    id: format!("order-{}", id),
+                    status: status.into(),
+                    side,
+                    price_cents,
+                    size,
+                    execution_type: execution_type.into(),
+                },
+            )
+    }
+
+    fn redis_levels(conn: &mut redis::Connection, side: BookSide) -> Vec<(i64, f64)> {
+        let prices: Vec<i64> = redis::cmd("ZRANGE")
+            .arg(side.prices_key())
+            .arg(0)
+            .arg(-1)
+            .query(conn)
+            .unwrap();
+        let sizes: BTreeMap<i64, f64> = redis::cmd("HGETALL")
+            .arg(side.sizes_key())
+            .query(conn)
+            .unwrap();
+        assert_eq!(
+            prices,
+            sizes.keys().copied().collect::<Vec<_>>(),
+            "{} prices and sizes differ",
+            side.key()
+        );
+        sizes.into_iter().collect()
+    }
+
+    fn assert_levels_eq(redis: &[(i64, f64)], model: &[(i64, f64)]) {
+        assert_eq!(redis.len(), model.len(), "{:?} != {:?}", redis, model);
+        for ((price, size), (model_price, model_size)) in redis.iter().zip(model) {
+            assert_eq!(price, model_price, "{:?} != {:?}", redis, model);
+            assert!(
+                (size - model_size).abs() < 1e-9,
+                "{:?} != {:?}",
+                redis,
+                model
+            );
+        }
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(200))]
+
+        /// Runs against database 15 of a local redis, the script works on fixed key names.
+        #[test]
+        #[ignore]
+        fn lua_script_matches_model(calls in prop::collection::vec(script_call(), 1..40)) {
+            let client = redis::Client::open("redis://localhost:6379/15").unwrap();
+            let mut conn = client.get_connection().unwrap();
+            redis::cmd("FLUSHDB").execute(&mut conn);
+            let script = redis::Script::new(UPDATE_FN);
+
+            let mut model = ScriptBook::default();
+            for call in calls.iter() {
+                script
+                    .arg(&call.id)
+                    .arg(&call.status)
+                    .arg(call.side.key())
+                    .arg(call.price_cents / 100)
+                    .arg(call.price_cents)
+                    .arg(call.size)
+                    .arg("2026-01-01T00:00:00Z")
+                    .arg(0)
+                    .arg(0)
+                    .arg(&call.execution_type)
+                    .invoke::<()>(&mut conn)
+                    .unwrap();
+                model.apply(call);
+            }
+
+            for side in [BookSide::Bid, BookSide::Ask] {
+                assert_levels_eq(&redis_levels(&mut conn, side), &model.levels(side));
+            }
+            let orders: BTreeMap<String, i64> = redis::cmd("HGETALL")
+                .arg("orders")
+                .query(&mut conn)
+                .unwrap();
+            prop_assert_eq!(orders, model.orders);
+        }
+    }
+}
@@ -34,6 +34,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    archiver_offset (consumer_group, topic, partition_id) {
+        consumer_group -> Varchar,
+        topic -> Varchar,
+        partition_id -> Int4,
+        last_offset -> Int8,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     btc_usd_price (id) {
         id -> Int8,
@@ -42,6 +52,22 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    candles (resolution, start_time) {
+        resolution -> Text,
+        start_time -> Timestamptz,
+        end_time -> Timestamptz,
+        low -> Numeric,
+        high -> Numeric,
+        open -> Numeric,
+        close -> Numeric,
+        trades -> Int4,
+        btc_volume -> Numeric,
+        usd_volume -> Numeric,
+        updated_at -> Timestamptz,
+    }
+}
+
 diesel::table! {
     candles_1day (start_time) {
         start_time -> Timestamptz,
@@ -109,11 +135,14 @@ diesel::table! {
         api_key -> Varchar,
         api_salt_key -> Varchar,
         created_on -> Timestamptz,
-        expires_on -> Timestamptz,
+        expires_on -> Nullable<Timestamptz>,
         is_active -> Bool,
         remark -> Nullable<Varchar>,
         authorities -> Nullable<Varchar>,
         limit_remaining -> Nullable<Int8>,
+        label -> Varchar,
+        last_used_on -> Nullable<Timestamptz>,
+        secret_key_id -> Nullable<Varchar>,
     }
 }
 
@@ -129,6 +158,21 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    dead_letter_event (id) {
+        id -> Int8,
+        topic -> Varchar,
+        partition_id -> Int4,
+        kafka_offset -> Int8,
+        event_key -> Varchar,
+        payload -> Text,
+        error -> Text,
+        created_at -> Timestamptz,
+        replay_requested -> Bool,
+        replayed_at -> Nullable<Timestamptz>,
+    }
+}
+
 diesel::table! {
     fee_history (id) {
         id -> Int8,
@@ -391,7 +435,9 @@ diesel::joinable!(customer_order_linking -> customer_account (customer_account_i
 
 diesel::allow_tables_to_appear_in_same_query!(
     address_customer_id,
+    archiver_offset,
     btc_usd_price,
+    candles,
     candles_1day,
     candles_1hour,
     candles_1min,
@@ -399,6 +445,7 @@ diesel::allow_tables_to_appear_in_same_query!(
     customer_account,
     customer_apikey_linking,
     customer_order_linking,
+    dead_letter_event,
     fee_history,
     funding_rate,
     lend_order,
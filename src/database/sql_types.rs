@@ -126,6 +126,14 @@ pub enum OrderStatus {
 }
 
 impl OrderStatus {
+    /// Statuses of the trader order events that count as a trade in candle volumes, see
+    /// `is_trade`.
+    pub const TRADES: [OrderStatus; 3] = [
+        OrderStatus::FILLED,
+        OrderStatus::SETTLED,
+        OrderStatus::LIQUIDATE,
+    ];
+
     pub fn as_str(&self) -> &'static str {
         use OrderStatus::*;
 
@@ -171,6 +179,12 @@ impl OrderStatus {
         }
     }
 
+    /// Whether an order event with this status is a trade, for both the materialized candles and
+    /// the live bar the websocket server builds.
+    pub fn is_trade(&self) -> bool {
+        Self::TRADES.contains(self)
+    }
+
     pub fn is_closed(&self) -> bool {
         use OrderStatus::*;
 
@@ -14,4 +14,8 @@ pub enum ApiError {
     R2d2(#[from] r2d2::Error),
     #[error("Redis error {0:?}")]
     Redis(#[from] redis::RedisError),
+    #[error("Order book changed during each of {0} repair attempts")]
+    BookRepairConflict(usize),
+    #[error("Api secret error {0}")]
+    Secret(#[from] crate::auth::secrets::SecretError),
 }
@@ -0,0 +1,325 @@
+use crate::kafka::{decode_event, Completion};
+use chrono::prelude::*;
+use crossbeam_channel::{unbounded, Receiver, Sender};
+use log::{error, info, trace};
+use relayer_core::db::Event;
+use serde::Deserialize;
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader},
+    path::PathBuf,
+    thread::{self, JoinHandle},
+};
+
+/// Number of file lines grouped into a single message set.
+const FILE_BATCH: usize = 512;
+
+/// A batch of events together with the completion that acknowledges it.
+pub type MessageSet = (Completion, Vec<Event>);
+
+/// Anything that can feed `Event`s to the archiver or the websocket watcher.
+pub trait EventSource {
+    /// Start delivering message sets on `tx`. Completions sent on the returned channel
+    /// acknowledge everything up to that offset on the partition. The source stops once `tx` is
+    /// disconnected, or once it runs out of events.
+    fn start(self, tx: Sender<MessageSet>) -> (Sender<Completion>, JoinHandle<()>);
+
+    /// Whether completions are kafka partition offsets that the archiver should store and resume
+    /// from. Other sources number their message sets by position in the source, which would
+    /// collide with real offsets in `archiver_offset`.
+    fn kafka_offsets(&self) -> bool {
+        false
+    }
+}
+
+/// Keep the completion channel open until every consumer is done with it, optionally forwarding
+/// completions to `acks`.
+fn drain_completions(rx: Receiver<Completion>, acks: Option<Sender<Completion>>) {
+    for completion in rx.iter() {
+        trace!("Completion {:?}", completion);
+        if let Some(acks) = &acks {
+            let _ = acks.send(completion);
+        }
+    }
+}
+
+/// Stable name of an event variant, used for per-type counts.
+pub fn event_name(event: &Event) -> &'static str {
+    match event {
+        Event::FeeUpdate(..) => "FeeUpdate",
+        Event::TraderOrder(..) => "TraderOrder",
+        Event::TraderOrderUpdate(..) => "TraderOrderUpdate",
+        Event::TraderOrderFundingUpdate(..) => "TraderOrderFundingUpdate",
+        Event::TraderOrderLimitUpdate(..) => "TraderOrderLimitUpdate",
+        Event::TraderOrderLiquidation(..) => "TraderOrderLiquidation",
+        Event::LendOrder(..) => "LendOrder",
+        Event::FundingRateUpdate(..) => "FundingRateUpdate",
+        Event::CurrentPriceUpdate(..) => "CurrentPriceUpdate",
+        Event::PoolUpdate(..) => "PoolUpdate",
+        Event::SortedSetDBUpdate(..) => "SortedSetDBUpdate",
+        Event::PositionSizeLogDBUpdate(..) => "PositionSizeLogDBUpdate",
+        Event::Stop(..) => "Stop",
+        Event::TxHash(..) => "TxHash",
+        Event::TxHashUpdate(..) => "TxHashUpdate",
+        Event::AdvanceStateQueue(..) => "AdvanceStateQueue",
+        Event::RiskEngineUpdate(..) => "RiskEngineUpdate",
+        Event::RiskParamsUpdate(..) => "RiskParamsUpdate",
+    }
+}
+
+/// The time an event carries, for the variants that have one.
+pub fn event_timestamp(event: &Event) -> Option<DateTime<Utc>> {
+    let ts = match event {
+        Event::FeeUpdate(_, time) => time,
+        Event::TraderOrder(order, ..)
+        | Event::TraderOrderUpdate(order, ..)
+        | Event::TraderOrderFundingUpdate(order, ..)
+        | Event::TraderOrderLimitUpdate(order, ..)
+        | Event::TraderOrderLiquidation(order, ..) => &order.timestamp,
+        Event::LendOrder(order, ..) => &order.timestamp,
+        Event::FundingRateUpdate(_, _, time) => time,
+        Event::CurrentPriceUpdate(_, time) => time,
+        Event::SortedSetDBUpdate(_, time) => time,
+        Event::TxHash(data) => &data.datetime,
+        Event::TxHashUpdate(data) => &data.datetime,
+        _ => return None,
+    };
+
+    DateTime::parse_from_rfc3339(ts)
+        .ok()
+        .map(|ts| ts.with_timezone(&Utc))
+}
+
+/// One line of a recorded event log, either a bare `Event` or the raw kafka record it came from.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RecordedLine {
+    Record {
+        #[serde(default)]
+        key: Option<String>,
+        #[serde(default)]
+        partition: i32,
+        offset: Option<i64>,
+        value: serde_json::Value,
+    },
+    Event(serde_json::Value),
+}
+
+/// Decode one line of a JSONL event log. Lines can either hold a serialized `Event`, or a record
+/// `{"key", "partition", "offset", "value"}` as dumped from kafka, in which case the key is used
+/// to upcast older event versions exactly as the kafka consumer does.
+pub fn decode_line(line: &str) -> Result<(Option<(i32, i64)>, Event), serde_json::Error> {
+    match serde_json::from_str(line)? {
+        RecordedLine::Record {
+            key,
+            partition,
+            offset,
+            value,
+        } => {
+            let payload = match value {
+                serde_json::Value::String(raw) => raw,
+                value => value.to_string(),
+            };
+            let key = key.unwrap_or_default();
+            let event = decode_event(key.as_bytes(), payload.as_bytes())?;
+
+            Ok((offset.map(|o| (partition, o)), event))
+        }
+        RecordedLine::Event(value) => Ok((None, serde_json::from_value(value)?)),
+    }
+}
+
+/// Replays a JSONL event log from disk, see `decode_line` for the accepted line formats.
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new<P: Into<PathBuf>>(path: P) -> FileSource {
+        FileSource { path: path.into() }
+    }
+
+    /// Read the whole log, calling `f` for each decoded event with its position in the log. Lines
+    /// that fail to decode are logged and skipped, returns how many there were.
+    pub fn for_each<F>(&self, mut f: F) -> io::Result<u64>
+    where
+        F: FnMut(Completion, Event) -> bool,
+    {
+        let reader = BufReader::new(File::open(&self.path)?);
+        let mut undecodable = 0;
+
+        for (line_no, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match decode_line(&line) {
+                Ok((position, event)) => {
+                    let completion = position.unwrap_or((0, line_no as i64));
+                    if !f(completion, event) {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    error!("Invalid event on line {}: {:?}", line_no + 1, e);
+                    undecodable += 1;
+                }
+            }
+        }
+
+        Ok(undecodable)
+    }
+}
+
+impl EventSource for FileSource {
+    fn start(self, tx: Sender<MessageSet>) -> (Sender<Completion>, JoinHandle<()>) {
+        let (tx_consumed, rx_consumed) = unbounded::<Completion>();
+
+        let handle = thread::spawn(move || {
+            info!("Replaying events from {:?}", self.path);
+
+            let mut batch = Vec::with_capacity(FILE_BATCH);
+            let mut last = (0, 0);
+            let result = self.for_each(|completion, event| {
+                last = completion;
+                batch.push(event);
+
+                if batch.len() < FILE_BATCH {
+                    return true;
+                }
+
+                let events = std::mem::replace(&mut batch, Vec::with_capacity(FILE_BATCH));
+                tx.send((completion, events)).is_ok()
+            });
+
+            match result {
+                Ok(0) => {}
+                Ok(undecodable) => {
+                    error!(
+                        "Skipped {} undecodable events in {:?}",
+                        undecodable, self.path
+                    )
+                }
+                Err(e) => error!("Failed to read {:?}: {:?}", self.path, e),
+            }
+            if !batch.is_empty() {
+                let _ = tx.send((last, batch));
+            }
+
+            info!("Finished replaying {:?}", self.path);
+            drop(tx);
+            drain_completions(rx_consumed, None);
+        });
+
+        (tx_consumed, handle)
+    }
+}
+
+/// In-memory source, every `Vec<Event>` sent on the channel becomes one message set on
+/// partition 0. Completions can be observed through `with_acks`.
+pub struct ChannelSource {
+    rx: Receiver<Vec<Event>>,
+    acks: Option<Sender<Completion>>,
+}
+
+impl ChannelSource {
+    pub fn new(rx: Receiver<Vec<Event>>) -> ChannelSource {
+        ChannelSource { rx, acks: None }
+    }
+
+    pub fn with_acks(mut self, acks: Sender<Completion>) -> ChannelSource {
+        self.acks = Some(acks);
+        self
+    }
+}
+
+impl EventSource for ChannelSource {
+    fn start(self, tx: Sender<MessageSet>) -> (Sender<Completion>, JoinHandle<()>) {
+        let (tx_consumed, rx_consumed) = unbounded::<Completion>();
+
+        let handle = thread::spawn(move || {
+            let ChannelSource { rx, acks } = self;
+
+            for (offset, events) in rx.iter().enumerate() {
+                if tx.send(((0, offset as i64), events)).is_err() {
+                    break;
+                }
+            }
+
+            drop(tx);
+            drain_completions(rx_consumed, acks);
+        });
+
+        (tx_consumed, handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn file_source_replays_every_line() {
+        let path = std::env::temp_dir().join(format!("events-{}.jsonl", uuid::Uuid::new_v4()));
+        {
+            let mut file = File::create(&path).unwrap();
+            let stop = serde_json::to_string(&Event::Stop("first".into())).unwrap();
+            writeln!(file, "{}", stop).unwrap();
+            writeln!(file, "not an event").unwrap();
+            let record = serde_json::json!({
+                "partition": 3,
+                "offset": 17,
+                "value": serde_json::to_string(&Event::Stop("second".into())).unwrap(),
+            });
+            writeln!(file, "{}", record).unwrap();
+        }
+
+        let (tx, rx) = unbounded();
+        let (completions, handle) = FileSource::new(&path).start(tx);
+
+        let (completion, events) = rx.recv().unwrap();
+        assert_eq!(completion, (3, 17));
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[0], Event::Stop(s) if s == "first"));
+        assert!(matches!(&events[1], Event::Stop(s) if s == "second"));
+        assert!(rx.recv().is_err());
+
+        completions.send(completion).unwrap();
+        drop(completions);
+        handle.join().unwrap();
+
+        let mut decoded = 0;
+        let undecodable = FileSource::new(&path)
+            .for_each(|_, _| {
+                decoded += 1;
+                true
+            })
+            .unwrap();
+        assert_eq!((decoded, undecodable), (2, 1));
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn channel_source_forwards_acks() {
+        let (events_tx, events_rx) = unbounded();
+        let (acks_tx, acks_rx) = unbounded();
+        let (tx, rx) = unbounded();
+
+        let (completions, handle) = ChannelSource::new(events_rx).with_acks(acks_tx).start(tx);
+
+        events_tx.send(vec![Event::Stop("one".into())]).unwrap();
+        let (completion, events) = rx.recv().unwrap();
+        assert_eq!(completion, (0, 0));
+        assert_eq!(events.len(), 1);
+
+        drop(events_tx);
+        completions.send(completion).unwrap();
+        drop(completions);
+        handle.join().unwrap();
+
+        assert_eq!(acks_rx.try_recv().unwrap(), (0, 0));
+    }
+}
@@ -1,112 +1,380 @@
-use crossbeam_channel::{unbounded, Sender};
+use crate::database::NewDeadLetterEvent;
+use crate::event_source::{EventSource, MessageSet};
+use crate::metrics;
+use crossbeam_channel::{unbounded, Receiver, Sender, TryRecvError};
+use kafka::client::KafkaClient;
 use kafka::consumer::{Consumer, FetchOffset, GroupOffsetStorage};
-use log::{error, info};
+use log::{error, info, warn};
 use relayer_core::db::{Event, EventKey};
+use std::collections::HashMap;
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
 use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Delay before reconnecting after a kafka error.
+const RECONNECT_DELAY: u64 = 5;
+/// How often consumer lag is refreshed.
+const LAG_INTERVAL: u64 = 10;
+/// How often the shutdown flag is checked while waiting to reconnect.
+const SHUTDOWN_POLL: u64 = 100;
 
 pub type Completion = (i32, i64);
 
+/// Decode a raw kafka message into an `Event`, upcasting older event versions first.
+pub fn decode_event(key: &[u8], value: &[u8]) -> Result<Event, serde_json::Error> {
+    let msg_key = String::from_utf8_lossy(key).to_string();
+    let mut eventkey = EventKey::from_string_or_default(msg_key);
+    let mut msg_data = String::from_utf8_lossy(value).to_string();
+
+    // Upcast old event versions to current format
+    while eventkey.is_upcast() {
+        msg_data = eventkey.event_log_upcast(msg_data);
+    }
+
+    serde_json::from_str(&msg_data)
+}
+
+/// Brokers from the comma separated `BROKER` variable, `localhost:9092` by default.
+pub fn brokers_from_env() -> Vec<String> {
+    std::env::var("BROKER")
+        .unwrap_or_else(|_| "localhost:9092".to_string())
+        .split(',')
+        .map(|s| s.trim().to_string())
+        .collect()
+}
+
 pub fn start_consumer(
     group: String,
     topic: String,
     tx: Sender<(Completion, Vec<Event>)>,
 ) -> (Sender<Completion>, JoinHandle<()>) {
-    let (tx_consumed, rx_consumed) = unbounded::<Completion>();
+    KafkaSource::from_env(group, topic).start(tx)
+}
+
+/// Reads events from a kafka topic as part of a consumer group.
+pub struct KafkaSource {
+    brokers: Vec<String>,
+    group: String,
+    topic: String,
+    fallback_offset: FetchOffset,
+    start_offsets: HashMap<i32, i64>,
+    dead_letters: Option<Sender<NewDeadLetterEvent>>,
+    shutdown: Arc<AtomicBool>,
+}
+
+impl KafkaSource {
+    /// Configure a source from the environment: `BROKER` is a comma separated broker list
+    /// (default `localhost:9092`) and `KAFKA_FALLBACK_OFFSET` is `earliest` (default) or `latest`,
+    /// used when the group has no committed offset yet.
+    pub fn from_env(group: String, topic: String) -> KafkaSource {
+        let brokers = brokers_from_env();
+
+        let fallback_offset = match std::env::var("KAFKA_FALLBACK_OFFSET").as_deref() {
+            Ok("latest") => FetchOffset::Latest,
+            _ => FetchOffset::Earliest,
+        };
+
+        KafkaSource {
+            brokers,
+            group,
+            topic,
+            fallback_offset,
+            start_offsets: HashMap::new(),
+            dead_letters: None,
+            shutdown: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    pub fn with_brokers(mut self, brokers: Vec<String>) -> KafkaSource {
+        self.brokers = brokers;
+        self
+    }
+
+    pub fn with_fallback_offset(mut self, fallback_offset: FetchOffset) -> KafkaSource {
+        self.fallback_offset = fallback_offset;
+        self
+    }
+
+    /// Resume after `offsets` (partition -> last applied offset). The group offsets are moved
+    /// there before the first poll, and any message at or below them is dropped, so events that
+    /// have already been applied are never delivered twice.
+    pub fn with_start_offsets(mut self, offsets: HashMap<i32, i64>) -> KafkaSource {
+        self.start_offsets = offsets;
+        self
+    }
+
+    /// Messages that fail to decode are always counted in `kafka_dead_letters_total`, with a sink
+    /// they're also sent there ahead of the message set they belong to.
+    pub fn with_dead_letters(mut self, sink: Sender<NewDeadLetterEvent>) -> KafkaSource {
+        self.dead_letters = Some(sink);
+        self
+    }
 
-    let handle = std::thread::spawn(move || {
-        let broker: Vec<String> = std::env::var("BROKER")
-            .unwrap_or_else(|_| "localhost:9092".to_string())
-            .split(',')
-            .map(|s| s.trim().to_string())
-            .collect();
+    /// Give up reconnecting once `flag` is set, so the consumer thread can be joined while the
+    /// brokers are unreachable.
+    pub fn with_shutdown(mut self, flag: Arc<AtomicBool>) -> KafkaSource {
+        self.shutdown = flag;
+        self
+    }
 
-        info!("Connecting to kafka at host: {}", broker.join(","));
+    fn connect(&self, seek: bool) -> Result<Consumer, kafka::error::Error> {
+        info!("Connecting to kafka at host: {}", self.brokers.join(","));
 
-        let mut con = Consumer::from_hosts(broker.clone())
-            .with_group(group)
-            .with_topic(topic.clone())
-            .with_fallback_offset(FetchOffset::Earliest)
+        let mut client = KafkaClient::new(self.brokers.clone());
+        client.set_group_offset_storage(GroupOffsetStorage::Kafka);
+        client.load_metadata_all()?;
+
+        if seek {
+            for (partition, offset) in self.start_offsets.iter() {
+                info!(
+                    "Seeking partition {} past stored offset {}",
+                    partition, offset
+                );
+                client.commit_offset(&self.group, &self.topic, *partition, offset + 1)?;
+            }
+        }
+
+        let mut con = Consumer::from_client(client)
+            .with_group(self.group.clone())
+            .with_topic(self.topic.clone())
+            .with_fallback_offset(self.fallback_offset)
             .with_offset_storage(GroupOffsetStorage::Kafka)
-            .create()
-            .unwrap();
+            .create()?;
+
+        con.client_mut().load_metadata_all()?;
+
+        Ok(con)
+    }
+
+    /// Connect, retrying until the broker is reachable. Returns `None` once shutdown has been
+    /// requested.
+    fn connect_with_retry(&self, seek: bool) -> Option<Consumer> {
+        loop {
+            if self.shutdown.load(Ordering::SeqCst) {
+                info!("Shutdown requested, no longer connecting to kafka");
+                return None;
+            }
+
+            match self.connect(seek) {
+                Ok(con) => return Some(con),
+                Err(e) => {
+                    error!("Kafka connection failed {:?}", e);
+                    self.wait_to_reconnect();
+                }
+            }
+        }
+    }
+
+    /// Sleep for `RECONNECT_DELAY`, waking up early on shutdown.
+    fn wait_to_reconnect(&self) {
+        let deadline = Instant::now() + Duration::from_secs(RECONNECT_DELAY);
+        while Instant::now() < deadline && !self.shutdown.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(SHUTDOWN_POLL));
+        }
+    }
+
+    fn dead_letter(&self, partition: i32, offset: i64, key: &[u8], value: &[u8], error: String) {
+        metrics::KAFKA_DEAD_LETTERS
+            .with_label_values(&[&self.topic])
+            .inc();
+        error!("Invalid message at {}:{}! {}", partition, offset, error);
 
-        con.client_mut().load_metadata_all().unwrap();
+        if let Some(sink) = &self.dead_letters {
+            let record = NewDeadLetterEvent {
+                topic: self.topic.clone(),
+                partition_id: partition,
+                kafka_offset: offset,
+                event_key: String::from_utf8_lossy(key).to_string(),
+                payload: String::from_utf8_lossy(value).to_string(),
+                error,
+            };
+            if let Err(e) = sink.send(record) {
+                error!("Dead-letter sink closed: {:?}", e);
+            }
+        }
+    }
+
+    /// Update the lag gauge from the latest offset of each partition and the last offset handed
+    /// downstream.
+    fn record_lag(&self, con: &mut Consumer, delivered: &HashMap<i32, i64>) {
+        let latest = match con
+            .client_mut()
+            .fetch_topic_offsets(&self.topic, FetchOffset::Latest)
+        {
+            Ok(latest) => latest,
+            Err(e) => {
+                warn!("Failed to fetch latest offsets for {}: {:?}", self.topic, e);
+                return;
+            }
+        };
+
+        for p in latest {
+            // `Latest` is the offset the next message will get.
+            let next = delivered.get(&p.partition).map_or(0, |offset| offset + 1);
+            metrics::KAFKA_CONSUMER_LAG
+                .with_label_values(&[&self.group, &self.topic, &p.partition.to_string()])
+                .set((p.offset - next).max(0));
+        }
+    }
+
+    /// Mark every completion received so far as consumed and commit them to the group. Returns
+    /// `false` once downstream has dropped the completion channel, i.e. it is shutting down.
+    fn commit_completions(
+        &self,
+        con: &mut Consumer,
+        rx_consumed: &Receiver<Completion>,
+    ) -> Result<bool, kafka::error::Error> {
+        let mut pending = false;
+
+        let open = loop {
+            match rx_consumed.try_recv() {
+                Ok((partition, offset)) => {
+                    con.consume_message(&self.topic, partition, offset)?;
+                    pending = true;
+                }
+                Err(TryRecvError::Empty) => break true,
+                Err(TryRecvError::Disconnected) => break false,
+            }
+        };
+
+        if pending {
+            con.commit_consumed()?;
+        }
+
+        Ok(open)
+    }
+}
+
+impl EventSource for KafkaSource {
+    fn start(self, tx: Sender<MessageSet>) -> (Sender<Completion>, JoinHandle<()>) {
+        let (tx_consumed, rx_consumed) = unbounded::<Completion>();
+
+        let handle = std::thread::spawn(move || {
+            // Highest offset handed downstream per partition, anything at or below is a
+            // re-delivery, either of already applied events or after a reconnect.
+            let mut delivered = self.start_offsets.clone();
+            let Some(mut con) = self.connect_with_retry(true) else {
+                return;
+            };
+            let mut lag_deadline = Instant::now();
+
+            'poll: loop {
+                if Instant::now() >= lag_deadline {
+                    self.record_lag(&mut con, &delivered);
+                    lag_deadline = Instant::now() + Duration::from_secs(LAG_INTERVAL);
+                }
+
+                let mss = match con.poll() {
+                    Ok(mss) => mss,
+                    Err(e) => {
+                        warn!("Kafka poll failed, reconnecting {:?}", e);
+                        self.wait_to_reconnect();
+                        match self.connect_with_retry(false) {
+                            Some(reconnected) => con = reconnected,
+                            None => break 'poll,
+                        }
+                        continue;
+                    }
+                };
 
-        let mut connection_status = true;
-        while connection_status {
-            let sender_clone = tx.clone();
-            let mss = con.poll().unwrap();
-            if !mss.is_empty() {
                 for ms in mss.iter() {
-                    let mut max_offset = 0i64;
+                    let partition = ms.partition();
+                    let applied = delivered.get(&partition).copied().unwrap_or(-1);
+                    let mut max_offset = applied;
 
                     let events: Vec<Event> = ms
                         .messages()
                         .iter()
-                        .map(|m| {
+                        .filter(|m| m.offset > applied)
+                        .filter_map(|m| {
                             max_offset = max_offset.max(m.offset);
 
-                            let msg_key = String::from_utf8_lossy(&m.key).to_string();
-                            let mut eventkey = EventKey::from_string_or_default(msg_key);
-                            let mut msg_data = String::from_utf8_lossy(&m.value).to_string();
-
-                            // Upcast old event versions to current format
-                            while eventkey.is_upcast() {
-                                msg_data = eventkey.event_log_upcast(msg_data);
-                            }
-
-                            let message: Event = match serde_json::from_str(&msg_data) {
-                                Ok(event) => event,
+                            match decode_event(m.key, m.value) {
+                                Ok(event) => Some(event),
                                 Err(e) => {
-                                    println!("Invalid message! {:?} {}\n", e, msg_data);
-                                    Event::Stop(e.to_string())
+                                    self.dead_letter(
+                                        partition,
+                                        m.offset,
+                                        m.key,
+                                        m.value,
+                                        e.to_string(),
+                                    );
+                                    None
                                 }
-                            };
-                            message
+                            }
                         })
                         .collect();
 
-                    let token = (ms.partition(), max_offset);
+                    if max_offset == applied {
+                        continue;
+                    }
+                    delivered.insert(partition, max_offset);
 
-                    match sender_clone.send((token, events)) {
-                        Ok(_) => {}
-                        Err(_arg) => {
-                            connection_status = false;
-                            break;
-                        }
+                    if tx.send(((partition, max_offset), events)).is_err() {
+                        break 'poll;
                     }
                 }
 
-                while !rx_consumed.is_empty() {
-                    match rx_consumed.recv() {
-                        Ok((partition, offset)) => {
-                            let e = con.consume_message(&topic, partition, offset);
-
-                            if e.is_err() {
-                                error!("Kafka connection failed {:?}", e);
-                                connection_status = false;
-                                break;
-                            }
-
-                            let e = con.commit_consumed();
-                            if e.is_err() {
-                                error!("Kafka connection failed {:?}", e);
-                                connection_status = false;
-                                break;
-                            }
-                        }
-                        Err(e) => {
-                            connection_status = false;
-                            error!("The consumed channel is closed: {:?}", e);
-                            break;
+                match self.commit_completions(&mut con, &rx_consumed) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        info!("Completion channel closed, stopping consumer");
+                        break 'poll;
+                    }
+                    Err(e) => {
+                        warn!("Kafka offset commit failed, reconnecting {:?}", e);
+                        match self.connect_with_retry(false) {
+                            Some(reconnected) => con = reconnected,
+                            None => break 'poll,
                         }
                     }
                 }
             }
-        }
-        con.commit_consumed().unwrap();
-        thread::park();
-    });
 
-    (tx_consumed, handle)
+            // Downstream is gone, keep committing its last completions until it lets go of the
+            // completion channel.
+            drop(tx);
+            while let Ok(completion) = rx_consumed.recv() {
+                let (partition, offset) = completion;
+                if let Err(e) = con.consume_message(&self.topic, partition, offset) {
+                    error!("Kafka connection failed {:?}", e);
+                }
+            }
+            if let Err(e) = con.commit_consumed() {
+                error!("Final kafka offset commit failed {:?}", e);
+            }
+        });
+
+        (tx_consumed, handle)
+    }
+
+    fn kafka_offsets(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn consumer_stops_retrying_on_shutdown() {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let source = KafkaSource::from_env("kafka-test".into(), "kafka-test".into())
+            .with_brokers(vec!["127.0.0.1:1".into()])
+            .with_shutdown(shutdown.clone());
+
+        let (tx, rx) = unbounded();
+        let (_completions, handle) = source.start(tx);
+        thread::sleep(Duration::from_millis(200));
+        shutdown.store(true, Ordering::SeqCst);
+
+        let started = Instant::now();
+        handle.join().unwrap();
+        assert!(started.elapsed() < Duration::from_secs(RECONNECT_DELAY));
+        assert!(rx.try_recv().is_err());
+    }
 }
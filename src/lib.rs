@@ -1,24 +1,15 @@
 mod archiver;
+pub mod auth;
+pub mod book_script;
 pub mod database;
 pub mod error;
+pub mod event_source;
 pub mod kafka;
+pub mod metrics;
+pub mod order_book;
 pub(crate) mod migrations;
+pub mod reconcile;
 pub mod rpc;
 pub mod ws;
 pub extern crate relayer_core;
 pub use archiver::DatabaseArchiver;
-
-pub mod auth {
-    use serde::{Deserialize, Serialize};
-
-    #[derive(Debug, Clone, Deserialize, Serialize)]
-    pub struct AuthInfo {
-        pub api_key: String,
-        pub api_secret: String,
-    }
-
-    #[derive(Debug, Clone, Deserialize, Serialize)]
-    pub struct UserInfo {
-        pub customer_id: i64,
-    }
-}
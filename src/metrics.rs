@@ -0,0 +1,264 @@
+use hyper::{
+    header,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use jsonrpsee::server::logger::{HttpRequest, Logger, MethodKind, Params, TransportProtocol};
+use log::{error, info};
+use prometheus::{
+    exponential_buckets, register_histogram, register_histogram_vec, register_int_counter,
+    register_int_counter_vec, register_int_gauge_vec, Encoder, Histogram, HistogramVec, IntCounter,
+    IntCounterVec, IntGaugeVec, TextEncoder,
+};
+use std::{
+    collections::HashSet,
+    convert::Infallible,
+    net::SocketAddr,
+    sync::{Arc, LazyLock},
+    time::Instant,
+};
+
+fn latency_buckets() -> Vec<f64> {
+    // 1ms .. ~16s
+    exponential_buckets(0.001, 2.0, 15).expect("valid buckets")
+}
+
+/// Events handled by `DatabaseArchiver::process_msg`, by event type.
+pub static ARCHIVER_EVENTS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "archiver_events_total",
+        "Events processed by the archiver",
+        &["event"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Time spent writing one batch to its table.
+pub static ARCHIVER_COMMIT_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "archiver_commit_duration_seconds",
+        "Time spent committing a batch to a table",
+        &["table"],
+        latency_buckets()
+    )
+    .expect("metric can be registered")
+});
+
+pub static ARCHIVER_COMMIT_FAILURES: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "archiver_commit_failures_total",
+        "Failed batch commits",
+        &["table"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Time spent on a whole commit transaction, every table plus the kafka offsets.
+pub static ARCHIVER_TRANSACTION_SECONDS: LazyLock<Histogram> = LazyLock::new(|| {
+    register_histogram!(
+        "archiver_transaction_duration_seconds",
+        "Time spent committing all pending batches in one transaction",
+        latency_buckets()
+    )
+    .expect("metric can be registered")
+});
+
+pub static ARCHIVER_CONNECTION_RETRIES: LazyLock<IntCounter> = LazyLock::new(|| {
+    register_int_counter!(
+        "archiver_connection_retries_total",
+        "Retries while checking out a postgres connection"
+    )
+    .expect("metric can be registered")
+});
+
+/// Failed runs of the order book lua script, by operation.
+pub static REDIS_SCRIPT_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "archiver_redis_script_errors_total",
+        "Order book script calls that failed",
+        &["operation"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Messages that could not be decoded and were handed to the dead-letter sink.
+pub static KAFKA_DEAD_LETTERS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "kafka_dead_letters_total",
+        "Kafka messages that failed to decode",
+        &["topic"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Entries of a redis order book key that disagreed with postgres on the last reconciliation.
+pub static ORDER_BOOK_DRIFT: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "order_book_drift",
+        "Redis order book entries that differ from postgres",
+        &["key"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Distance between the newest message on a partition and the last one handed downstream.
+pub static KAFKA_CONSUMER_LAG: LazyLock<IntGaugeVec> = LazyLock::new(|| {
+    register_int_gauge_vec!(
+        "kafka_consumer_lag",
+        "Messages on the partition not yet delivered to the consumer",
+        &["group", "topic", "partition"]
+    )
+    .expect("metric can be registered")
+});
+
+pub static RPC_SECONDS: LazyLock<HistogramVec> = LazyLock::new(|| {
+    register_histogram_vec!(
+        "rpc_request_duration_seconds",
+        "JSON-RPC method latency",
+        &["server", "method"],
+        latency_buckets()
+    )
+    .expect("metric can be registered")
+});
+
+pub static RPC_ERRORS: LazyLock<IntCounterVec> = LazyLock::new(|| {
+    register_int_counter_vec!(
+        "rpc_errors_total",
+        "JSON-RPC calls that returned an error",
+        &["server", "method"]
+    )
+    .expect("metric can be registered")
+});
+
+/// Time `f`, recording the duration against `table` and counting a failure if it errors.
+pub fn observe_commit<T, E>(table: &str, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    let timer = ARCHIVER_COMMIT_SECONDS
+        .with_label_values(&[table])
+        .start_timer();
+    let result = f();
+    timer.observe_duration();
+
+    if result.is_err() {
+        ARCHIVER_COMMIT_FAILURES.with_label_values(&[table]).inc();
+    }
+
+    result
+}
+
+/// Records per-method latency and error counts for a JSON-RPC server. Calls to methods that
+/// aren't registered are grouped under `unknown` to keep label cardinality bounded.
+#[derive(Clone)]
+pub struct RpcMetrics {
+    server: &'static str,
+    methods: Arc<HashSet<&'static str>>,
+}
+
+impl RpcMetrics {
+    pub fn new(server: &'static str, methods: impl Iterator<Item = &'static str>) -> Self {
+        RpcMetrics {
+            server,
+            methods: Arc::new(methods.collect()),
+        }
+    }
+
+    fn method<'a>(&self, name: &'a str) -> &'a str {
+        if self.methods.contains(name) {
+            name
+        } else {
+            "unknown"
+        }
+    }
+}
+
+impl Logger for RpcMetrics {
+    type Instant = Instant;
+
+    fn on_connect(&self, _remote_addr: SocketAddr, _request: &HttpRequest, _t: TransportProtocol) {}
+
+    fn on_request(&self, _transport: TransportProtocol) -> Self::Instant {
+        Instant::now()
+    }
+
+    fn on_call(&self, _name: &str, _params: Params, _kind: MethodKind, _t: TransportProtocol) {}
+
+    fn on_result(&self, name: &str, success: bool, started_at: Instant, _t: TransportProtocol) {
+        let labels = [self.server, self.method(name)];
+
+        RPC_SECONDS
+            .with_label_values(&labels)
+            .observe(started_at.elapsed().as_secs_f64());
+        if !success {
+            RPC_ERRORS.with_label_values(&labels).inc();
+        }
+    }
+
+    fn on_response(&self, _result: &str, _started_at: Instant, _t: TransportProtocol) {}
+
+    fn on_disconnect(&self, _remote_addr: SocketAddr, _t: TransportProtocol) {}
+}
+
+/// Render every registered metric in the prometheus text format.
+pub fn render() -> Vec<u8> {
+    let mut buffer = Vec::new();
+    if let Err(e) = TextEncoder::new().encode(&prometheus::gather(), &mut buffer) {
+        error!("Failed to encode metrics: {:?}", e);
+    }
+
+    buffer
+}
+
+async fn handle(req: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::GET, "/metrics") => Response::builder()
+            .header(header::CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(Body::from(render())),
+        _ => Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty()),
+    };
+
+    Ok(response.expect("valid response"))
+}
+
+/// Serve `GET /metrics` on `addr`.
+pub async fn serve(addr: SocketAddr) -> Result<(), hyper::Error> {
+    info!("Serving metrics on {:?}", addr);
+    let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+
+    Server::try_bind(&addr)?.serve(make_service).await
+}
+
+/// Serve metrics from a dedicated thread, for binaries that don't run a tokio runtime.
+pub fn serve_in_background(addr: SocketAddr) -> std::thread::JoinHandle<()> {
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .expect("Failed to build metrics runtime");
+
+        if let Err(e) = runtime.block_on(serve(addr)) {
+            error!("Metrics server failed: {:?}", e);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rpc_metrics_bucket_unknown_methods() {
+        let metrics = RpcMetrics::new("test", ["server_time"].into_iter());
+        let started_at = Instant::now();
+
+        metrics.on_result("server_time", true, started_at, TransportProtocol::Http);
+        metrics.on_result("no_such_method", false, started_at, TransportProtocol::Http);
+
+        let rendered = String::from_utf8(render()).unwrap();
+        assert!(rendered.contains(
+            r#"rpc_request_duration_seconds_count{method="server_time",server="test"} 1"#
+        ));
+        assert!(rendered.contains(r#"rpc_errors_total{method="unknown",server="test"} 1"#));
+        assert!(!rendered.contains("no_such_method"));
+    }
+}
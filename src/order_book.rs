@@ -0,0 +1,430 @@
+use log::error;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Most levels per side a client can ask for.
+pub const MAX_BOOK_DEPTH: usize = 1000;
+
+/// Which side of the book a level is on, its redis keys are prefixed with the name.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BookSide {
+    Bid,
+    Ask,
+}
+
+impl BookSide {
+    pub fn key(&self) -> &'static str {
+        match self {
+            BookSide::Bid => "bid",
+            BookSide::Ask => "ask",
+        }
+    }
+
+    /// Sorted set of the prices in cents with a level on this side, scored by price.
+    pub fn prices_key(&self) -> &'static str {
+        match self {
+            BookSide::Bid => "bid:prices",
+            BookSide::Ask => "ask:prices",
+        }
+    }
+
+    /// Hash of price in cents to the total size resting at that price.
+    pub fn sizes_key(&self) -> &'static str {
+        match self {
+            BookSide::Bid => "bid:sizes",
+            BookSide::Ask => "ask:sizes",
+        }
+    }
+}
+
+/// Aggregated size resting at a price, `cumulative` adds up the sizes from the best level down
+/// to this one.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Level {
+    pub price: f64,
+    pub positionsize: f64,
+    pub cumulative: f64,
+}
+
+/// Both sides of the book, best first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BookDepth {
+    pub bid: Vec<Level>,
+    pub ask: Vec<Level>,
+}
+
+/// A level whose size changed, `positionsize` 0 means the level is gone.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LevelDelta {
+    pub side: BookSide,
+    pub price: f64,
+    pub positionsize: f64,
+}
+
+/// Messages of the `subscribe_order_book` feed. A subscription starts with a snapshot, every
+/// delta after it has `seq` one higher than the message before. A client that sees a gap should
+/// discard its book and resync with `get_order_book_snapshot`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum BookMessage {
+    Snapshot {
+        seq: u64,
+        bid: Vec<Level>,
+        ask: Vec<Level>,
+    },
+    Delta {
+        seq: u64,
+        changes: Vec<LevelDelta>,
+    },
+}
+
+impl BookMessage {
+    pub fn seq(&self) -> u64 {
+        match self {
+            BookMessage::Snapshot { seq, .. } | BookMessage::Delta { seq, .. } => *seq,
+        }
+    }
+}
+
+/// Optional params of `open_limit_orders` and `subscribe_order_book`: how many price levels per
+/// side, and the bucket width in dollars to group them into.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct OrderBookArgs {
+    pub depth: Option<usize>,
+    pub tick: Option<f64>,
+}
+
+/// A `tick` that isn't a positive whole number of cents.
+#[derive(Debug, thiserror::Error)]
+#[error("tick must be a positive multiple of 0.01, got {0}")]
+pub struct InvalidTick(pub f64);
+
+/// How a client wants to see the book: levels grouped into buckets `tick_cents` wide, and only
+/// the best `depth` buckets of each side.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BookView {
+    pub depth: usize,
+    pub tick_cents: i64,
+}
+
+impl Default for BookView {
+    fn default() -> Self {
+        BookView {
+            depth: MAX_BOOK_DEPTH,
+            tick_cents: 1,
+        }
+    }
+}
+
+impl BookView {
+    /// `args.tick` is in dollars and has to be a whole number of cents, `args.depth` is clamped
+    /// to `1..=MAX_BOOK_DEPTH`.
+    pub fn new(args: OrderBookArgs, default_depth: usize) -> Result<BookView, InvalidTick> {
+        let depth = args.depth.unwrap_or(default_depth).clamp(1, MAX_BOOK_DEPTH);
+
+        let tick_cents = match args.tick {
+            None => 1,
+            Some(tick) => {
+                let cents = (tick * 100.0).round();
+                if !tick.is_finite() || cents < 1.0 || (tick * 100.0 - cents).abs() > 1e-6 {
+                    return Err(InvalidTick(tick));
+                }
+                cents as i64
+            }
+        };
+
+        Ok(BookView { depth, tick_cents })
+    }
+}
+
+/// Price levels of both sides keyed by price in cents, as stored in redis.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BookLevels {
+    bid: BTreeMap<i64, f64>,
+    ask: BTreeMap<i64, f64>,
+}
+
+impl BookLevels {
+    /// Read every level of the redis book: the prices of a side are the members of its
+    /// `<side>:prices` sorted set, their sizes the values of its `<side>:sizes` hash.
+    pub fn load(conn: &mut redis::Connection) -> redis::RedisResult<BookLevels> {
+        Self::read(conn, true)
+    }
+
+    /// `load` without a MULTI of its own, whose EXEC would drop a WATCH on the book keys. The
+    /// caller's watch has to make the reads consistent instead.
+    pub fn load_watched(conn: &mut redis::Connection) -> redis::RedisResult<BookLevels> {
+        Self::read(conn, false)
+    }
+
+    fn read(conn: &mut redis::Connection, atomic: bool) -> redis::RedisResult<BookLevels> {
+        let mut levels = BookLevels::default();
+
+        let mut pipe = redis::pipe();
+        if atomic {
+            pipe.atomic();
+        }
+        for side in [BookSide::Bid, BookSide::Ask] {
+            pipe.cmd("ZRANGE").arg(side.prices_key()).arg(0).arg(-1);
+            pipe.cmd("HGETALL").arg(side.sizes_key());
+        }
+        let (bid_prices, bid_sizes, ask_prices, ask_sizes): (
+            Vec<i64>,
+            BTreeMap<i64, String>,
+            Vec<i64>,
+            BTreeMap<i64, String>,
+        ) = pipe.query(conn)?;
+
+        for (side, prices, sizes) in [
+            (BookSide::Bid, bid_prices, bid_sizes),
+            (BookSide::Ask, ask_prices, ask_sizes),
+        ] {
+            let book = levels.side_mut(side);
+            for cents in prices {
+                let Some(size) = sizes.get(&cents) else {
+                    error!("No {} size at {}", side.key(), cents);
+                    continue;
+                };
+                match size.parse::<f64>() {
+                    Ok(size) => {
+                        book.insert(cents, size);
+                    }
+                    Err(e) => error!("Bad {} level size {:?}: {:?}", side.key(), size, e),
+                }
+            }
+        }
+
+        Ok(levels)
+    }
+
+    pub fn insert(&mut self, side: BookSide, price_cents: i64, positionsize: f64) {
+        self.side_mut(side).insert(price_cents, positionsize);
+    }
+
+    /// Add `positionsize` to the level at `price_cents`, creating it if needed.
+    pub fn add(&mut self, side: BookSide, price_cents: i64, positionsize: f64) {
+        *self.side_mut(side).entry(price_cents).or_default() += positionsize;
+    }
+
+    /// `(price_cents, positionsize)` of every level, lowest price first.
+    pub fn iter(&self, side: BookSide) -> impl Iterator<Item = (i64, f64)> + '_ {
+        self.side(side).iter().map(|(cents, size)| (*cents, *size))
+    }
+
+    /// Apply deltas produced by `diff`.
+    pub fn apply(&mut self, changes: &[LevelDelta]) {
+        for change in changes {
+            let cents = (change.price * 100.0).round() as i64;
+            let book = self.side_mut(change.side);
+            if change.positionsize == 0.0 {
+                book.remove(&cents);
+            } else {
+                book.insert(cents, change.positionsize);
+            }
+        }
+    }
+
+    /// The book as `view` sees it. Bids are rounded down and asks up to their bucket, so a
+    /// bucket never quotes a better price than the orders in it.
+    pub fn view(&self, view: BookView) -> BookLevels {
+        let tick = view.tick_cents;
+        let mut grouped = BookLevels::default();
+
+        for side in [BookSide::Bid, BookSide::Ask] {
+            let book = grouped.side_mut(side);
+
+            for (cents, size) in self.best_first(side) {
+                let bucket = match side {
+                    BookSide::Bid => cents.div_euclid(tick) * tick,
+                    BookSide::Ask => -((-cents).div_euclid(tick) * tick),
+                };
+                // Buckets come best first too, once a new one doesn't fit the rest won't either.
+                if !book.contains_key(&bucket) && book.len() == view.depth {
+                    break;
+                }
+                *book.entry(bucket).or_default() += size;
+            }
+        }
+
+        grouped
+    }
+
+    /// Levels best first: bids from the highest price, asks from the lowest.
+    pub fn levels(&self, side: BookSide) -> Vec<Level> {
+        let mut cumulative = 0.0;
+        self.best_first(side)
+            .map(|(cents, size)| {
+                cumulative += size;
+                Level {
+                    price: *cents as f64 / 100.0,
+                    positionsize: *size,
+                    cumulative,
+                }
+            })
+            .collect()
+    }
+
+    pub fn depth(&self) -> BookDepth {
+        BookDepth {
+            bid: self.levels(BookSide::Bid),
+            ask: self.levels(BookSide::Ask),
+        }
+    }
+
+    pub fn snapshot(&self, seq: u64) -> BookMessage {
+        let BookDepth { bid, ask } = self.depth();
+        BookMessage::Snapshot { seq, bid, ask }
+    }
+
+    /// Changes turning `self` into `next`.
+    pub fn diff(&self, next: &BookLevels) -> Vec<LevelDelta> {
+        let mut changes = Vec::new();
+
+        for side in [BookSide::Bid, BookSide::Ask] {
+            let (old, new) = (self.side(side), next.side(side));
+
+            for (cents, size) in new.iter() {
+                if old.get(cents) != Some(size) {
+                    changes.push(LevelDelta {
+                        side,
+                        price: *cents as f64 / 100.0,
+                        positionsize: *size,
+                    });
+                }
+            }
+            for cents in old.keys().filter(|cents| !new.contains_key(cents)) {
+                changes.push(LevelDelta {
+                    side,
+                    price: *cents as f64 / 100.0,
+                    positionsize: 0.0,
+                });
+            }
+        }
+
+        changes
+    }
+
+    fn best_first(&self, side: BookSide) -> Box<dyn Iterator<Item = (&i64, &f64)> + '_> {
+        match side {
+            BookSide::Bid => Box::new(self.bid.iter().rev()),
+            BookSide::Ask => Box::new(self.ask.iter()),
+        }
+    }
+
+    fn side(&self, side: BookSide) -> &BTreeMap<i64, f64> {
+        match side {
+            BookSide::Bid => &self.bid,
+            BookSide::Ask => &self.ask,
+        }
+    }
+
+    fn side_mut(&mut self, side: BookSide) -> &mut BTreeMap<i64, f64> {
+        match side {
+            BookSide::Bid => &mut self.bid,
+            BookSide::Ask => &mut self.ask,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_levels_into_ticks_best_first() {
+        let mut levels = BookLevels::default();
+        levels.insert(BookSide::Bid, 4_200_050, 1.0);
+        levels.insert(BookSide::Bid, 4_200_999, 2.0);
+        levels.insert(BookSide::Bid, 4_199_000, 4.0);
+        levels.insert(BookSide::Bid, 4_100_000, 8.0);
+        levels.insert(BookSide::Ask, 4_300_001, 1.0);
+        levels.insert(BookSide::Ask, 4_301_000, 2.0);
+
+        let view = BookView::new(
+            OrderBookArgs {
+                depth: Some(2),
+                tick: Some(10.0),
+            },
+            MAX_BOOK_DEPTH,
+        )
+        .unwrap();
+        let BookDepth { bid, ask } = levels.view(view).depth();
+
+        let bid: Vec<_> = bid
+            .iter()
+            .map(|l| (l.price, l.positionsize, l.cumulative))
+            .collect();
+        assert_eq!(bid, vec![(42_000.0, 3.0, 3.0), (41_990.0, 4.0, 7.0)]);
+        // Asks round up, so 43000.01 is quoted at 43010 along with the level there.
+        let ask: Vec<_> = ask
+            .iter()
+            .map(|l| (l.price, l.positionsize, l.cumulative))
+            .collect();
+        assert_eq!(ask, vec![(43_010.0, 3.0, 3.0)]);
+
+        for tick in [0.0, -1.0, 0.005, f64::NAN] {
+            let args = OrderBookArgs {
+                depth: None,
+                tick: Some(tick),
+            };
+            assert!(BookView::new(args, MAX_BOOK_DEPTH).is_err());
+        }
+
+        // Replaying a diff onto the old book gives the new one.
+        let mut next = levels.clone();
+        next.insert(BookSide::Ask, 4_305_000, 6.0);
+        next.apply(&[LevelDelta {
+            side: BookSide::Bid,
+            price: 41_000.0,
+            positionsize: 0.0,
+        }]);
+        let mut replayed = levels.clone();
+        replayed.apply(&levels.diff(&next));
+        assert_eq!(replayed, next);
+    }
+
+    #[test]
+    #[ignore]
+    fn loads_levels_from_redis() {
+        // A database of its own, the test clears the book keys.
+        let client = redis::Client::open("redis://localhost:6379/15").unwrap();
+        let mut conn = client.get_connection().unwrap();
+        redis::cmd("DEL")
+            .arg("bid:prices")
+            .arg("bid:sizes")
+            .arg("ask:prices")
+            .arg("ask:sizes")
+            .execute(&mut conn);
+        for (side, cents, size) in [("bid", 4_200_000, 12.5), ("ask", 4_300_000, 8.0)] {
+            redis::cmd("ZADD")
+                .arg(format!("{}:prices", side))
+                .arg(cents)
+                .arg(cents)
+                .execute(&mut conn);
+            redis::cmd("HSET")
+                .arg(format!("{}:sizes", side))
+                .arg(cents)
+                .arg(size)
+                .execute(&mut conn);
+        }
+        // A size without a price isn't a level.
+        redis::cmd("HSET")
+            .arg("ask:sizes")
+            .arg(4_400_000)
+            .arg(3)
+            .execute(&mut conn);
+
+        let levels = BookLevels::load(&mut conn).unwrap();
+        assert_eq!(
+            levels.levels(BookSide::Bid),
+            vec![Level {
+                price: 42_000.0,
+                positionsize: 12.5,
+                cumulative: 12.5,
+            }]
+        );
+        assert_eq!(levels.levels(BookSide::Ask).len(), 1);
+        assert_eq!(levels.levels(BookSide::Ask)[0].positionsize, 8.0);
+    }
+}
@@ -0,0 +1,363 @@
+use crate::{
+    database::{PositionType, TraderOrder},
+    error::ApiError,
+    metrics,
+    order_book::{BookLevels, BookSide},
+};
+use bigdecimal::ToPrimitive;
+use diesel::prelude::{PgConnection, QueryResult};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Times `reconcile_order_book` rereads both stores when the archiver changes the book under a
+/// repair.
+const REPAIR_ATTEMPTS: usize = 5;
+
+/// The order book as the archiver's redis keys hold it: the aggregated levels of each side, and
+/// the `orders` hash mapping each resting order to its price in cents.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BookState {
+    pub levels: BookLevels,
+    pub orders: BTreeMap<String, i64>,
+}
+
+/// A level whose size in redis isn't what postgres implies, 0 meaning the level is missing.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LevelDrift {
+    pub side: BookSide,
+    pub price: f64,
+    pub expected: f64,
+    pub actual: f64,
+}
+
+/// An entry of the `orders` hash that is missing, extra or at the wrong price.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OrderDrift {
+    pub uuid: String,
+    pub expected: Option<f64>,
+    pub actual: Option<f64>,
+}
+
+/// Differences between the redis book and the one rebuilt from postgres.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct BookReport {
+    pub levels: Vec<LevelDrift>,
+    pub orders: Vec<OrderDrift>,
+    /// Whether redis was rewritten from postgres afterwards.
+    pub repaired: bool,
+}
+
+impl BookReport {
+    pub fn is_consistent(&self) -> bool {
+        self.levels.is_empty() && self.orders.is_empty()
+    }
+}
+
+impl BookState {
+    /// Add a resting order. Prices and sizes are truncated the same way the archiver truncates
+    /// them when it calls the order book script.
+    pub fn add_order(&mut self, uuid: &str, side: BookSide, price: f64, positionsize: f64) {
+        let price_cents = (price * 100.0) as i64;
+
+        self.levels.add(side, price_cents, positionsize.trunc());
+        self.orders.insert(uuid.to_string(), price_cents);
+    }
+
+    /// The book implied by the `orderbook` view: open limit orders, and filled orders with a
+    /// close limit price on the opposite side.
+    pub fn from_postgres(conn: &mut PgConnection) -> QueryResult<BookState> {
+        let mut book = BookState::default();
+
+        for order in TraderOrder::order_book_orders(conn)? {
+            let side = match order.position_type {
+                PositionType::LONG => BookSide::Bid,
+                PositionType::SHORT => BookSide::Ask,
+            };
+            book.add_order(
+                &order.uuid,
+                side,
+                order.entryprice.to_f64().unwrap_or_default(),
+                order.positionsize.to_f64().unwrap_or_default(),
+            );
+        }
+
+        Ok(book)
+    }
+
+    /// Every redis key of the book.
+    pub fn redis_keys() -> Vec<&'static str> {
+        let mut keys = vec!["orders"];
+        for side in [BookSide::Bid, BookSide::Ask] {
+            keys.push(side.prices_key());
+            keys.push(side.sizes_key());
+        }
+        keys
+    }
+
+    /// Read the book from redis. The reads aren't atomic by themselves, the caller WATCHes
+    /// `redis_keys` first so a `write` after a concurrent change fails.
+    pub fn from_redis(conn: &mut redis::Connection) -> redis::RedisResult<BookState> {
+        let levels = BookLevels::load_watched(conn)?;
+        let entries: Vec<(String, f64)> = redis::cmd("HGETALL").arg("orders").query(conn)?;
+        let orders = entries
+            .into_iter()
+            .map(|(uuid, cents)| (uuid, cents.round() as i64))
+            .collect();
+
+        Ok(BookState { levels, orders })
+    }
+
+    /// What is wrong with `actual` when `self` is the expected book.
+    pub fn compare(&self, actual: &BookState) -> BookReport {
+        let mut report = BookReport::default();
+
+        for side in [BookSide::Bid, BookSide::Ask] {
+            let expected: BTreeMap<_, _> = self.levels.iter(side).collect();
+            let actual: BTreeMap<_, _> = actual.levels.iter(side).collect();
+
+            let prices: BTreeSet<i64> = expected.keys().chain(actual.keys()).copied().collect();
+
+            for cents in prices {
+                let want = expected.get(&cents).copied().unwrap_or_default();
+                let have = actual.get(&cents).copied().unwrap_or_default();
+                if want != have {
+                    report.levels.push(LevelDrift {
+                        side,
+                        price: cents as f64 / 100.0,
+                        expected: want,
+                        actual: have,
+                    });
+                }
+            }
+        }
+
+        for (uuid, cents) in self.orders.iter() {
+            if actual.orders.get(uuid) != Some(cents) {
+                report.orders.push(OrderDrift {
+                    uuid: uuid.clone(),
+                    expected: Some(*cents as f64 / 100.0),
+                    actual: actual.orders.get(uuid).map(|c| *c as f64 / 100.0),
+                });
+            }
+        }
+        for (uuid, cents) in actual.orders.iter() {
+            if !self.orders.contains_key(uuid) {
+                report.orders.push(OrderDrift {
+                    uuid: uuid.clone(),
+                    expected: None,
+                    actual: Some(*cents as f64 / 100.0),
+                });
+            }
+        }
+
+        report
+    }
+
+    /// Replace the redis order book keys with this book in one transaction. Returns false, having
+    /// written nothing, when a key WATCHed on `conn` changed since.
+    pub fn write(&self, conn: &mut redis::Connection) -> redis::RedisResult<bool> {
+        let mut pipe = redis::pipe();
+        pipe.atomic();
+        pipe.cmd("DEL").arg("orders").ignore();
+
+        for side in [BookSide::Bid, BookSide::Ask] {
+            pipe.cmd("DEL")
+                .arg(side.prices_key())
+                .arg(side.sizes_key())
+                .ignore();
+
+            let mut zadd = redis::cmd("ZADD");
+            zadd.arg(side.prices_key());
+            let mut hset = redis::cmd("HSET");
+            hset.arg(side.sizes_key());
+            let mut empty = true;
+            for (cents, size) in self.levels.iter(side) {
+                zadd.arg(cents).arg(cents);
+                hset.arg(cents).arg(size);
+                empty = false;
+            }
+            if !empty {
+                pipe.add_command(zadd).ignore();
+                pipe.add_command(hset).ignore();
+            }
+        }
+
+        if !self.orders.is_empty() {
+            let mut hset = redis::cmd("HSET");
+            hset.arg("orders");
+            for (uuid, cents) in self.orders.iter() {
+                hset.arg(uuid).arg(cents);
+            }
+            pipe.add_command(hset).ignore();
+        }
+
+        // EXEC replies nil when the transaction was aborted by a watched key.
+        let written: Option<()> = pipe.query(conn)?;
+        Ok(written.is_some())
+    }
+}
+
+/// Rebuild the order book from postgres and compare it with redis, rewriting redis when
+/// `repair` is set and the two disagree.
+///
+/// The book keys are WATCHed before either store is read, so a script call the archiver makes
+/// before the rewrite aborts it, and both stores are read again. Orders the archiver has applied
+/// to one store but not yet the other still show up as drift.
+pub fn reconcile_order_book(
+    conn: &mut PgConnection,
+    redis_conn: &mut redis::Connection,
+    repair: bool,
+) -> Result<BookReport, ApiError> {
+    for attempt in 1..=REPAIR_ATTEMPTS {
+        redis::cmd("WATCH")
+            .arg(BookState::redis_keys())
+            .query::<()>(redis_conn)?;
+
+        let report = compare_and_repair(conn, redis_conn, repair);
+        // A rewrite's EXEC has dropped the watch already, this covers the passes without one.
+        redis::cmd("UNWATCH").query::<()>(redis_conn)?;
+        if let Some(report) = report? {
+            return Ok(report);
+        }
+        warn!(
+            "Order book changed during repair attempt {}, reading it again",
+            attempt
+        );
+    }
+
+    Err(ApiError::BookRepairConflict(REPAIR_ATTEMPTS))
+}
+
+/// One pass of `reconcile_order_book` with the book keys watched. `None` means the rewrite was
+/// aborted because the archiver changed the book in between.
+fn compare_and_repair(
+    conn: &mut PgConnection,
+    redis_conn: &mut redis::Connection,
+    repair: bool,
+) -> Result<Option<BookReport>, ApiError> {
+    let expected = BookState::from_postgres(conn)?;
+    let actual = BookState::from_redis(redis_conn)?;
+    let mut report = expected.compare(&actual);
+
+    for side in [BookSide::Bid, BookSide::Ask] {
+        let drift = report.levels.iter().filter(|l| l.side == side).count();
+        metrics::ORDER_BOOK_DRIFT
+            .with_label_values(&[side.key()])
+            .set(drift as i64);
+    }
+    metrics::ORDER_BOOK_DRIFT
+        .with_label_values(&["orders"])
+        .set(report.orders.len() as i64);
+
+    if report.is_consistent() {
+        info!("Order book in redis matches postgres");
+        return Ok(Some(report));
+    }
+
+    warn!(
+        "Order book in redis differs from postgres: {} levels, {} orders",
+        report.levels.len(),
+        report.orders.len()
+    );
+    if repair {
+        if !expected.write(redis_conn)? {
+            return Ok(None);
+        }
+        report.repaired = true;
+        info!("Rewrote the redis order book from postgres");
+    }
+
+    Ok(Some(report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_level_and_order_drift() {
+        let mut expected = BookState::default();
+        expected.add_order("a", BookSide::Bid, 42_000.0, 100.0);
+        expected.add_order("b", BookSide::Bid, 42_000.0, 50.9);
+        expected.add_order("c", BookSide::Ask, 43_000.0, 70.0);
+
+        let mut actual = expected.clone();
+        assert!(expected.compare(&actual).is_consistent());
+
+        // A cancel that reached postgres but not redis, and a stale order redis never dropped.
+        actual.add_order("d", BookSide::Ask, 44_000.0, 10.0);
+        actual.levels.insert(BookSide::Bid, 4_200_000, 100.0);
+        actual.orders.remove("b");
+
+        let report = expected.compare(&actual);
+        assert_eq!(
+            report.levels,
+            vec![
+                LevelDrift {
+                    side: BookSide::Bid,
+                    price: 42_000.0,
+                    expected: 150.0,
+                    actual: 100.0,
+                },
+                LevelDrift {
+                    side: BookSide::Ask,
+                    price: 44_000.0,
+                    expected: 0.0,
+                    actual: 10.0,
+                },
+            ]
+        );
+        assert_eq!(
+            report.orders,
+            vec![
+                OrderDrift {
+                    uuid: "b".into(),
+                    expected: Some(42_000.0),
+                    actual: None,
+                },
+                OrderDrift {
+                    uuid: "d".into(),
+                    expected: None,
+                    actual: Some(44_000.0),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn rewrites_redis_atomically() {
+        // A database of its own, the test replaces the book keys.
+        let client = redis::Client::open("redis://localhost:6379/15").unwrap();
+        let mut conn = client.get_connection().unwrap();
+        redis::cmd("HSET")
+            .arg("bid:sizes")
+            .arg(100)
+            .arg(1)
+            .execute(&mut conn);
+
+        let mut book = BookState::default();
+        book.add_order("a", BookSide::Bid, 42_000.0, 100.0);
+        book.add_order("b", BookSide::Ask, 43_000.0, 70.0);
+        assert!(book.write(&mut conn).unwrap());
+
+        assert_eq!(BookState::from_redis(&mut conn).unwrap(), book);
+
+        // A script call between the watch and the rewrite aborts it.
+        let mut archiver = client.get_connection().unwrap();
+        redis::cmd("WATCH")
+            .arg(BookState::redis_keys())
+            .execute(&mut conn);
+        redis::cmd("HSET")
+            .arg("orders")
+            .arg("c")
+            .arg(4_400_000)
+            .execute(&mut archiver);
+        assert!(!BookState::default().write(&mut conn).unwrap());
+
+        let mut expected = book.clone();
+        expected.orders.insert("c".into(), 4_400_000);
+        assert_eq!(BookState::from_redis(&mut conn).unwrap(), expected);
+    }
+}
@@ -0,0 +1,64 @@
+use super::error::RpcError;
+use super::types::{DeadLetterArgs, DeadLetterList, ReconcileOrderBookArgs, ReplayDeadLetterArgs};
+use super::*;
+use crate::database::*;
+use crate::reconcile::reconcile_order_book;
+use jsonrpsee::{core::error::Error, server::logger::Params};
+
+pub(super) fn dead_letters(
+    params: Params<'_>,
+    ctx: &RelayerContext,
+) -> Result<serde_json::Value, Error> {
+    let args: DeadLetterArgs = params
+        .parse()
+        .map_err(|e| RpcError::InvalidArgument(format!("{:?}", e)))?;
+    let limit = args.limit.clamp(1, MAX_PAGE_LIMIT);
+
+    match ctx.pool.get() {
+        Ok(mut conn) => {
+            let pending = DeadLetterEvent::count(&mut conn).map_err(RpcError::from)?;
+            let events =
+                DeadLetterEvent::list(&mut conn, args.include_replayed, limit, args.offset.max(0))
+                    .map_err(RpcError::from)?;
+
+            let list = DeadLetterList { pending, events };
+            Ok(serde_json::to_value(list).expect("Error converting response"))
+        }
+        Err(e) => Err(RpcError::from(e).into()),
+    }
+}
+
+pub(super) fn replay_dead_letters(
+    params: Params<'_>,
+    ctx: &RelayerContext,
+) -> Result<serde_json::Value, Error> {
+    let args: ReplayDeadLetterArgs = params
+        .parse()
+        .map_err(|e| RpcError::InvalidArgument(format!("{:?}", e)))?;
+
+    match ctx.pool.get() {
+        Ok(mut conn) => match DeadLetterEvent::request_replay(&mut conn, args.ids) {
+            Ok(scheduled) => Ok(serde_json::json!({ "scheduled": scheduled })),
+            Err(e) => Err(RpcError::from(e).into()),
+        },
+        Err(e) => Err(RpcError::from(e).into()),
+    }
+}
+
+pub(super) fn reconcile_order_book_cache(
+    params: Params<'_>,
+    ctx: &RelayerContext,
+) -> Result<serde_json::Value, Error> {
+    let args = params
+        .parse::<Option<ReconcileOrderBookArgs>>()
+        .map_err(|e| RpcError::InvalidArgument(format!("{:?}", e)))?
+        .unwrap_or_default();
+
+    let mut conn = ctx.pool.get().map_err(RpcError::from)?;
+    let mut redis_conn = ctx.client.get_connection().map_err(RpcError::from)?;
+
+    let report =
+        reconcile_order_book(&mut conn, &mut redis_conn, args.repair).map_err(RpcError::from)?;
+
+    Ok(serde_json::to_value(report).expect("Error converting response"))
+}
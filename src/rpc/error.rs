@@ -0,0 +1,246 @@
+use crate::error::ApiError;
+use crate::order_book::InvalidTick;
+use jsonrpsee::{
+    core::Error,
+    types::{error::CallError, ErrorObject, ErrorObjectOwned},
+};
+use serde::Serialize;
+
+/// Errors returned by the RPC methods. Every variant has a stable numeric code, clients should
+/// match on `code` (or on `data.kind`) rather than on the message.
+///
+/// | code   | kind                  |
+/// |--------|-----------------------|
+/// | -32000 | shutting_down         |
+/// | -32001 | invalid_hex           |
+/// | -32002 | invalid_bincode       |
+/// | -32003 | verification_failed   |
+/// | -32004 | not_found             |
+/// | -32005 | order_not_found       |
+/// | -32006 | order_closed          |
+/// | -32007 | order_not_cancelable  |
+/// | -32008 | forbidden             |
+/// | -32009 | invalid_argument      |
+/// | -32010 | database_unavailable  |
+/// | -32011 | database              |
+/// | -32012 | redis_unavailable     |
+/// | -32013 | kafka_send_failed     |
+/// | -32014 | serialization         |
+/// | -32603 | internal              |
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RpcError {
+    #[error("Server is shutting down")]
+    ShuttingDown,
+    #[error("Invalid hex data")]
+    InvalidHex,
+    #[error("Invalid bincode")]
+    InvalidBincode,
+    #[error("Invalid order params")]
+    VerificationFailed(String),
+    #[error("Not found")]
+    NotFound,
+    #[error("Order not found")]
+    OrderNotFound,
+    #[error("Order closed")]
+    OrderClosed,
+    #[error("Order not cancelable")]
+    OrderNotCancelable,
+    #[error("Api key lacks the required scope")]
+    Forbidden(String),
+    #[error("Database connection error")]
+    DatabaseUnavailable(String),
+    #[error("Database error")]
+    Database(String),
+    #[error("Redis connection error")]
+    RedisUnavailable(String),
+    #[error("Could not send order")]
+    KafkaSendFailed(String),
+    #[error("Could not serialize response")]
+    Serialization,
+    #[error("Invalid argument")]
+    InvalidArgument(String),
+    #[error("Internal error")]
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorData<'a> {
+    kind: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+}
+
+impl RpcError {
+    pub fn code(&self) -> i32 {
+        match self {
+            RpcError::ShuttingDown => -32000,
+            RpcError::InvalidHex => -32001,
+            RpcError::InvalidBincode => -32002,
+            RpcError::VerificationFailed(_) => -32003,
+            RpcError::NotFound => -32004,
+            RpcError::OrderNotFound => -32005,
+            RpcError::OrderClosed => -32006,
+            RpcError::OrderNotCancelable => -32007,
+            RpcError::Forbidden(_) => -32008,
+            RpcError::DatabaseUnavailable(_) => -32010,
+            RpcError::Database(_) => -32011,
+            RpcError::RedisUnavailable(_) => -32012,
+            RpcError::KafkaSendFailed(_) => -32013,
+            RpcError::Serialization => -32014,
+            RpcError::InvalidArgument(_) => -32009,
+            RpcError::Internal(_) => -32603,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            RpcError::ShuttingDown => "shutting_down",
+            RpcError::InvalidHex => "invalid_hex",
+            RpcError::InvalidBincode => "invalid_bincode",
+            RpcError::VerificationFailed(_) => "verification_failed",
+            RpcError::NotFound => "not_found",
+            RpcError::OrderNotFound => "order_not_found",
+            RpcError::OrderClosed => "order_closed",
+            RpcError::OrderNotCancelable => "order_not_cancelable",
+            RpcError::Forbidden(_) => "forbidden",
+            RpcError::DatabaseUnavailable(_) => "database_unavailable",
+            RpcError::Database(_) => "database",
+            RpcError::RedisUnavailable(_) => "redis_unavailable",
+            RpcError::KafkaSendFailed(_) => "kafka_send_failed",
+            RpcError::Serialization => "serialization",
+            RpcError::InvalidArgument(_) => "invalid_argument",
+            RpcError::Internal(_) => "internal",
+        }
+    }
+
+    /// Like `From<diesel::result::Error>`, but a missing row means the order doesn't exist.
+    pub fn order_lookup(e: diesel::result::Error) -> RpcError {
+        match RpcError::from(e) {
+            RpcError::NotFound => RpcError::OrderNotFound,
+            e => e,
+        }
+    }
+
+    fn detail(&self) -> Option<&str> {
+        match self {
+            RpcError::VerificationFailed(detail)
+            | RpcError::Forbidden(detail)
+            | RpcError::DatabaseUnavailable(detail)
+            | RpcError::Database(detail)
+            | RpcError::RedisUnavailable(detail)
+            | RpcError::KafkaSendFailed(detail)
+            | RpcError::InvalidArgument(detail)
+            | RpcError::Internal(detail) => Some(detail.as_str()),
+            _ => None,
+        }
+    }
+}
+
+impl From<RpcError> for ErrorObjectOwned {
+    fn from(e: RpcError) -> Self {
+        let data = ErrorData {
+            kind: e.kind(),
+            detail: e.detail(),
+        };
+        ErrorObject::owned(e.code(), e.to_string(), Some(data))
+    }
+}
+
+impl From<RpcError> for Error {
+    fn from(e: RpcError) -> Self {
+        Error::Call(CallError::Custom(e.into()))
+    }
+}
+
+impl From<diesel::result::Error> for RpcError {
+    fn from(e: diesel::result::Error) -> Self {
+        match e {
+            diesel::result::Error::NotFound => RpcError::NotFound,
+            e => RpcError::Database(format!("{:?}", e)),
+        }
+    }
+}
+
+impl From<r2d2::Error> for RpcError {
+    fn from(e: r2d2::Error) -> Self {
+        RpcError::DatabaseUnavailable(format!("{:?}", e))
+    }
+}
+
+impl From<redis::RedisError> for RpcError {
+    fn from(e: redis::RedisError) -> Self {
+        RpcError::RedisUnavailable(format!("{:?}", e))
+    }
+}
+
+impl From<InvalidTick> for RpcError {
+    fn from(e: InvalidTick) -> Self {
+        RpcError::InvalidArgument(e.to_string())
+    }
+}
+
+impl From<ApiError> for RpcError {
+    fn from(e: ApiError) -> Self {
+        match e {
+            ApiError::DatabaseError(e) => e.into(),
+            ApiError::R2d2(e) => e.into(),
+            ApiError::Redis(e) => e.into(),
+            e => RpcError::Internal(format!("{:?}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_to_error_object_with_code_and_data() {
+        let object = ErrorObjectOwned::from(RpcError::VerificationFailed("bad sig".into()));
+        assert_eq!(object.code(), -32003);
+        assert_eq!(object.message(), "Invalid order params");
+        let data: serde_json::Value = serde_json::from_str(object.data().unwrap().get()).unwrap();
+        assert_eq!(
+            data,
+            serde_json::json!({ "kind": "verification_failed", "detail": "bad sig" })
+        );
+
+        let object = ErrorObjectOwned::from(RpcError::from(diesel::result::Error::NotFound));
+        assert_eq!(object.code(), -32004);
+
+        let Error::Call(CallError::Custom(object)) = Error::from(RpcError::InvalidHex) else {
+            panic!("expected a custom call error");
+        };
+        assert_eq!(object.code(), -32001);
+        assert_eq!(object.message(), "Invalid hex data");
+    }
+
+    #[test]
+    fn application_errors_stay_out_of_the_reserved_range() {
+        // -32099..-32000 is left to the server by the JSON-RPC spec, -32603 marks panics.
+        let errors = [
+            RpcError::ShuttingDown,
+            RpcError::InvalidHex,
+            RpcError::InvalidBincode,
+            RpcError::VerificationFailed(String::new()),
+            RpcError::NotFound,
+            RpcError::OrderNotFound,
+            RpcError::OrderClosed,
+            RpcError::OrderNotCancelable,
+            RpcError::Forbidden(String::new()),
+            RpcError::DatabaseUnavailable(String::new()),
+            RpcError::Database(String::new()),
+            RpcError::RedisUnavailable(String::new()),
+            RpcError::KafkaSendFailed(String::new()),
+            RpcError::Serialization,
+            RpcError::InvalidArgument(String::new()),
+        ];
+
+        let mut codes: Vec<i32> = errors.iter().map(RpcError::code).collect();
+        assert!(codes.iter().all(|code| (-32099..=-32000).contains(code)));
+        codes.sort();
+        codes.dedup();
+        assert_eq!(codes.len(), errors.len());
+        assert_eq!(RpcError::InvalidArgument(String::new()).code(), -32009);
+    }
+}
@@ -0,0 +1,308 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, TryLockError};
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use chrono::prelude::*;
+use hyper::{header, Body, Method, Request, Response, StatusCode};
+use kafka::client::KafkaClient;
+use serde::Serialize;
+use tower::{Layer, Service};
+
+use super::RelayerContext;
+use crate::database::BtcUsdPrice;
+
+/// How long a single dependency check may take before it counts as failed.
+const CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+/// How long a broker metadata check is reused before probes query the brokers again.
+const KAFKA_CHECK_TTL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Ok,
+    /// Dependencies are reachable but the data is stale, e.g. the archiver stopped.
+    Degraded,
+    Unavailable,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Check {
+    pub ok: bool,
+    pub latency_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Check {
+    fn run<F>(f: F) -> Check
+    where
+        F: FnOnce() -> Result<(), String>,
+    {
+        let start = Instant::now();
+        let result = f();
+
+        Check {
+            ok: result.is_ok(),
+            latency_ms: start.elapsed().as_millis() as u64,
+            error: result.err(),
+        }
+    }
+
+    fn failed(error: String) -> Check {
+        Check {
+            ok: false,
+            latency_ms: CHECK_TIMEOUT.as_millis() as u64,
+            error: Some(error),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub status: Status,
+    pub postgres: Check,
+    pub redis: Check,
+    pub kafka: Check,
+    /// Seconds since the newest `btc_usd_price` row, `None` if it couldn't be read.
+    pub price_age_secs: Option<i64>,
+    pub max_price_age_secs: i64,
+}
+
+impl HealthReport {
+    fn new(
+        postgres: Check,
+        redis: Check,
+        kafka: Check,
+        price_age_secs: Option<i64>,
+        max_price_age_secs: i64,
+    ) -> Self {
+        let status = if !(postgres.ok && redis.ok && kafka.ok) {
+            Status::Unavailable
+        } else if price_age_secs.map_or(true, |age| age > max_price_age_secs) {
+            Status::Degraded
+        } else {
+            Status::Ok
+        };
+
+        HealthReport {
+            status,
+            postgres,
+            redis,
+            kafka,
+            price_age_secs,
+            max_price_age_secs,
+        }
+    }
+}
+
+/// Checks the dependencies of a `RelayerContext`: a pool connection, redis `PING`, the kafka
+/// producer and broker metadata, and the age of the latest archived price. The broker metadata
+/// is fetched at most once per `KAFKA_CHECK_TTL`, so probes can't flood the brokers.
+#[derive(Clone)]
+pub struct HealthCheck {
+    ctx: Arc<RelayerContext>,
+    max_price_age: Duration,
+    brokers: Arc<Mutex<Option<(Instant, Result<(), String>)>>>,
+}
+
+impl HealthCheck {
+    pub fn new(ctx: RelayerContext, max_price_age: Duration) -> HealthCheck {
+        HealthCheck {
+            ctx: Arc::new(ctx),
+            max_price_age,
+            brokers: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    pub fn layer(&self) -> HealthLayer {
+        HealthLayer {
+            check: self.clone(),
+        }
+    }
+
+    fn check_postgres(ctx: &RelayerContext) -> (Check, Option<i64>) {
+        let mut latest = None;
+        let check = Check::run(|| {
+            let mut conn = ctx
+                .pool
+                .get_timeout(CHECK_TIMEOUT)
+                .map_err(|e| e.to_string())?;
+            // An empty table is no reason to report postgres as down, it shows up as a missing
+            // price age instead.
+            if let Ok(price) = BtcUsdPrice::get(&mut conn) {
+                latest = Some(price.timestamp);
+            }
+            Ok(())
+        });
+
+        let age = latest.map(|ts: DateTime<Utc>| (Utc::now() - ts).num_seconds());
+        (check, age)
+    }
+
+    fn check_redis(ctx: &RelayerContext) -> Check {
+        Check::run(|| {
+            let mut conn = ctx
+                .client
+                .get_connection_with_timeout(CHECK_TIMEOUT)
+                .map_err(|e| e.to_string())?;
+            redis::cmd("PING")
+                .query::<String>(&mut conn)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+    }
+
+    fn check_kafka(&self) -> Check {
+        Check::run(|| {
+            // Busy means a send is in flight, only a poisoned producer is unusable.
+            if let Err(TryLockError::Poisoned(_)) = self.ctx.kafka.try_lock() {
+                return Err("kafka producer mutex poisoned".to_string());
+            }
+
+            // Held while the brokers are queried, concurrent probes wait for the same result.
+            let mut cached = self.brokers.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some((checked_at, result)) = cached.as_ref() {
+                if checked_at.elapsed() < KAFKA_CHECK_TTL {
+                    return result.clone();
+                }
+            }
+
+            let mut client = KafkaClient::new(self.ctx.brokers.clone());
+            let result = client.load_metadata_all().map_err(|e| e.to_string());
+            *cached = Some((Instant::now(), result.clone()));
+            result
+        })
+    }
+
+    async fn blocking<T, F>(f: F, fallback: impl FnOnce(String) -> T) -> T
+    where
+        T: Send + 'static,
+        F: FnOnce() -> T + Send + 'static,
+    {
+        match tokio::time::timeout(CHECK_TIMEOUT, tokio::task::spawn_blocking(f)).await {
+            Ok(Ok(result)) => result,
+            Ok(Err(e)) => fallback(format!("check panicked: {:?}", e)),
+            Err(_) => fallback(format!("timed out after {:?}", CHECK_TIMEOUT)),
+        }
+    }
+
+    pub async fn report(&self) -> HealthReport {
+        let (pg_ctx, redis_ctx, check) = (self.ctx.clone(), self.ctx.clone(), self.clone());
+
+        let ((postgres, price_age), redis, kafka) = tokio::join!(
+            Self::blocking(
+                move || Self::check_postgres(&pg_ctx),
+                |e| (Check::failed(e), None)
+            ),
+            Self::blocking(move || Self::check_redis(&redis_ctx), Check::failed),
+            Self::blocking(move || check.check_kafka(), Check::failed),
+        );
+
+        HealthReport::new(
+            postgres,
+            redis,
+            kafka,
+            price_age,
+            self.max_price_age.as_secs() as i64,
+        )
+    }
+}
+
+/// Answers `GET /health` and `GET /ready`, every other request goes to the RPC server.
+///
+/// `/health` always returns 200 so a liveness probe doesn't restart the api over a dependency
+/// outage, `/ready` returns 503 while postgres, redis or kafka are unreachable. Both return the
+/// full `HealthReport`.
+#[derive(Clone)]
+pub struct HealthLayer {
+    check: HealthCheck,
+}
+
+impl<S> Layer<S> for HealthLayer {
+    type Service = HealthService<S>;
+    fn layer(&self, inner: S) -> Self::Service {
+        HealthService {
+            inner,
+            check: self.check.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct HealthService<S> {
+    inner: S,
+    check: HealthCheck,
+}
+
+impl<S> Service<Request<Body>> for HealthService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Send,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let readiness = match (req.method(), req.uri().path()) {
+            (&Method::GET, "/health") => false,
+            (&Method::GET, "/ready") => true,
+            _ => {
+                let mut inner = self.inner.clone();
+                return Box::pin(async move { inner.call(req).await });
+            }
+        };
+
+        let check = self.check.clone();
+        Box::pin(async move {
+            let report = check.report().await;
+            let status = if readiness && report.status == Status::Unavailable {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            };
+
+            let body = serde_json::to_vec(&report).expect("Failed to serialize health report");
+            Ok(Response::builder()
+                .status(status)
+                .header(header::CONTENT_TYPE, "application/json")
+                .body(Body::from(body))
+                .expect("valid response"))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(ok: bool) -> Check {
+        Check {
+            ok,
+            latency_ms: 1,
+            error: None,
+        }
+    }
+
+    #[test]
+    fn stale_prices_degrade_and_outages_take_precedence() {
+        let report = HealthReport::new(check(true), check(true), check(true), Some(5), 60);
+        assert_eq!(report.status, Status::Ok);
+
+        let report = HealthReport::new(check(true), check(true), check(true), Some(600), 60);
+        assert_eq!(report.status, Status::Degraded);
+
+        let report = HealthReport::new(check(true), check(true), check(true), None, 60);
+        assert_eq!(report.status, Status::Degraded);
+
+        let report = HealthReport::new(check(true), check(false), check(true), Some(600), 60);
+        assert_eq!(report.status, Status::Unavailable);
+    }
+}
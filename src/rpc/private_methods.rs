@@ -1,3 +1,4 @@
+use super::error::RpcError;
 use super::*;
 use crate::database::*;
 use jsonrpsee::{core::error::Error, server::logger::Params};
@@ -20,15 +21,15 @@ pub(super) fn submit_lend_order(
     let Order { data } = order;
 
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
 
     let Ok(tx) = bincode::deserialize::<relayer::CreateLendOrderZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
 
-    if let Err(_) = verify_trade_lend_order(&tx.input) {
-        return Ok(format!("Invalid order params").into());
+    if let Err(e) = verify_trade_lend_order(&tx.input) {
+        return Err(RpcError::VerificationFailed(format!("{:?}", e)).into());
     }
 
     let mut order = tx.create_lend_order.clone();
@@ -39,16 +40,12 @@ pub(super) fn submit_lend_order(
         "Order request submitted successfully".to_string(),
         public_key,
     );
-    let Ok(response_value) = serde_json::to_value(&response) else {
-        return Ok(format!("Invalid response").into());
-    };
+    let response_value = serde_json::to_value(&response).map_err(|_| RpcError::Serialization)?;
 
-    let Ok(mut conn) = ctx.pool.get() else {
-        return Ok(format!("Database connection error").into());
-    };
+    let mut conn = ctx.pool.get().map_err(RpcError::from)?;
 
-    if let Err(_) = AddressCustomerId::insert(&mut conn, customer_id, &order.account_id) {
-        return Ok(format!("Failed to update customer id!").into());
+    if let Err(e) = AddressCustomerId::insert(&mut conn, customer_id, &order.account_id) {
+        return Err(RpcError::from(e).into());
     }
 
     let order = relayer::RpcCommand::CreateLendOrder(
@@ -57,13 +54,11 @@ pub(super) fn submit_lend_order(
         tx.input.encode_as_hex_string(),
         response.get_id(),
     );
-    let Ok(serialized) = serde_json::to_vec(&order) else {
-        return Ok(format!("Could not serialize order").into());
-    };
+    let serialized = serde_json::to_vec(&order).map_err(|_| RpcError::Serialization)?;
 
     let record = Record::from_key_value(&topic, "CreateLendOrder", serialized);
     if let Err(e) = ctx.kafka.lock().expect("Lock poisoned!").send(&record) {
-        Ok(format!("Could not send order {:?}", e).into())
+        Err(RpcError::KafkaSendFailed(format!("{:?}", e)).into())
     } else {
         Ok(response_value)
     }
@@ -80,15 +75,15 @@ pub(super) fn settle_lend_order(
     let Order { data } = order;
 
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
 
     let Ok(tx) = bincode::deserialize::<relayer::ExecuteLendOrderZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
 
-    if let Err(_) = verify_settle_requests(&tx.msg) {
-        return Ok(format!("Invalid order params").into());
+    if let Err(e) = verify_settle_requests(&tx.msg) {
+        return Err(RpcError::VerificationFailed(format!("{:?}", e)).into());
     }
 
     let order = tx.execute_lend_order.clone();
@@ -97,25 +92,20 @@ pub(super) fn settle_lend_order(
         "Order request submitted successfully".to_string(),
         public_key,
     );
-    let Ok(response_value) = serde_json::to_value(&response) else {
-        return Ok(format!("Invalid response").into());
-    };
-    let Ok(mut conn) = ctx.pool.get() else {
-        return Ok(format!("Database connection error").into());
-    };
+    let response_value = serde_json::to_value(&response).map_err(|_| RpcError::Serialization)?;
+    let mut conn = ctx.pool.get().map_err(RpcError::from)?;
 
-    let Ok(ord) = LendOrder::get(
+    let ord = LendOrder::get(
         &mut conn,
         customer_id,
         OrderId {
             id: order.uuid.to_string(),
         },
-    ) else {
-        return Ok(format!("Order not found").into());
-    };
+    )
+    .map_err(RpcError::order_lookup)?;
 
     if !ord.order_status.is_closed() {
-        return Ok(format!("Order closed").into());
+        return Err(RpcError::OrderClosed.into());
     }
 
     let meta = super::headers::meta_from_headers();
@@ -126,13 +116,11 @@ pub(super) fn settle_lend_order(
         tx.msg.encode_as_hex_string(),
         response.get_id(),
     );
-    let Ok(serialized) = serde_json::to_vec(&order) else {
-        return Ok(format!("Could not serialize order").into());
-    };
+    let serialized = serde_json::to_vec(&order).map_err(|_| RpcError::Serialization)?;
 
     let record = Record::from_key_value(&topic, "ExecuteLendOrder", serialized);
     if let Err(e) = ctx.kafka.lock().expect("Lock poisoned!").send(&record) {
-        Ok(format!("Could not send order {:?}", e).into())
+        Err(RpcError::KafkaSendFailed(format!("{:?}", e)).into())
     } else {
         Ok(response_value)
     }
@@ -149,19 +137,19 @@ pub(super) fn submit_trade_order(
     let Order { data } = order;
 
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
 
     let Ok(tx) = bincode::deserialize::<relayer::CreateTraderOrderClientZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
 
-    if let Err(_) = verify_client_create_trader_order(&tx.tx) {
-        return Ok(format!("Invalid order params").into());
+    if let Err(e) = verify_client_create_trader_order(&tx.tx) {
+        return Err(RpcError::VerificationFailed(format!("{:?}", e)).into());
     }
 
     let Ok(transaction_ser) = bincode::serialize(&tx.tx) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::Serialization.into());
     };
 
     let mut order = tx.create_trader_order.clone();
@@ -172,15 +160,11 @@ pub(super) fn submit_trade_order(
         "Order request submitted successfully".to_string(),
         public_key,
     );
-    let Ok(response_value) = serde_json::to_value(&response) else {
-        return Ok(format!("Invalid response").into());
-    };
-    let Ok(mut conn) = ctx.pool.get() else {
-        return Ok(format!("Database connection error").into());
-    };
+    let response_value = serde_json::to_value(&response).map_err(|_| RpcError::Serialization)?;
+    let mut conn = ctx.pool.get().map_err(RpcError::from)?;
 
-    if let Err(_) = AddressCustomerId::insert(&mut conn, customer_id, &order.account_id) {
-        return Ok(format!("Failed to update customer id!").into());
+    if let Err(e) = AddressCustomerId::insert(&mut conn, customer_id, &order.account_id) {
+        return Err(RpcError::from(e).into());
     }
 
     let order = relayer::RpcCommand::CreateTraderOrder(
@@ -189,13 +173,11 @@ pub(super) fn submit_trade_order(
         hex::encode(transaction_ser),
         response.get_id(),
     );
-    let Ok(serialized) = serde_json::to_vec(&order) else {
-        return Ok(format!("Could not serialize order").into());
-    };
+    let serialized = serde_json::to_vec(&order).map_err(|_| RpcError::Serialization)?;
 
     let record = Record::from_key_value(&topic, "CreateTraderOrder", serialized);
     if let Err(e) = ctx.kafka.lock().expect("Lock poisoned!").send(&record) {
-        Ok(format!("Could not send order {:?}", e).into())
+        Err(RpcError::KafkaSendFailed(format!("{:?}", e)).into())
     } else {
         Ok(response_value)
     }
@@ -212,7 +194,7 @@ pub(super) fn settle_trade_order(
     let Order { data } = order;
 
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
 
     // Try deserializing as SlTp variant first, then fall back to plain variant
@@ -222,11 +204,11 @@ pub(super) fn settle_trade_order(
         } else if let Ok(tx) = bincode::deserialize::<relayer::ExecuteTraderOrderZkos>(&bytes) {
             (tx.execute_trader_order, tx.msg, None)
         } else {
-            return Ok(format!("Invalid bincode").into());
+            return Err(RpcError::InvalidBincode.into());
         };
 
-    if let Err(_) = verify_settle_requests(&msg) {
-        return Ok(format!("Invalid order params").into());
+    if let Err(e) = verify_settle_requests(&msg) {
+        return Err(RpcError::VerificationFailed(format!("{:?}", e)).into());
     }
 
     let public_key = execute_order.account_id.clone();
@@ -234,19 +216,14 @@ pub(super) fn settle_trade_order(
         "Order request submitted successfully".to_string(),
         public_key,
     );
-    let Ok(response_value) = serde_json::to_value(&response) else {
-        return Ok(format!("Invalid response").into());
-    };
-    let Ok(mut conn) = ctx.pool.get() else {
-        return Ok(format!("Database connection error").into());
-    };
+    let response_value = serde_json::to_value(&response).map_err(|_| RpcError::Serialization)?;
+    let mut conn = ctx.pool.get().map_err(RpcError::from)?;
 
-    let Ok(ord) = TraderOrder::get(&mut conn, customer_id, execute_order.uuid.to_string()) else {
-        return Ok(format!("Order not found").into());
-    };
+    let ord = TraderOrder::get(&mut conn, customer_id, execute_order.uuid.to_string())
+        .map_err(RpcError::order_lookup)?;
 
     if !ord.order_status.is_closed() {
-        return Ok(format!("Order closed").into());
+        return Err(RpcError::OrderClosed.into());
     }
 
     let meta = super::headers::meta_from_headers();
@@ -276,13 +253,11 @@ pub(super) fn settle_trade_order(
         )
     };
 
-    let Ok(serialized) = serde_json::to_vec(&rpc_command) else {
-        return Ok(format!("Could not serialize order").into());
-    };
+    let serialized = serde_json::to_vec(&rpc_command).map_err(|_| RpcError::Serialization)?;
 
     let record = Record::from_key_value(&topic, record_key, serialized);
     if let Err(e) = ctx.kafka.lock().expect("Lock poisoned!").send(&record) {
-        Ok(format!("Could not send order {:?}", e).into())
+        Err(RpcError::KafkaSendFailed(format!("{:?}", e)).into())
     } else {
         Ok(response_value)
     }
@@ -299,7 +274,7 @@ pub(super) fn cancel_trader_order(
     let Order { data } = order;
 
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
 
     // Try deserializing as SlTp variant first, then fall back to plain variant
@@ -309,14 +284,14 @@ pub(super) fn cancel_trader_order(
         } else if let Ok(tx) = bincode::deserialize::<relayer::CancelTraderOrderZkos>(&bytes) {
             (tx.cancel_trader_order, tx.msg, None)
         } else {
-            return Ok(format!("Invalid bincode").into());
+            return Err(RpcError::InvalidBincode.into());
         };
 
-    if let Err(_) = verify_query_order(
+    if let Err(e) = verify_query_order(
         msg.convert_cancel_to_query(),
         &bincode::serialize(&cancel_order).unwrap(),
     ) {
-        return Ok(format!("Invalid order params").into());
+        return Err(RpcError::VerificationFailed(format!("{:?}", e)).into());
     }
 
     let public_key = cancel_order.account_id.clone();
@@ -324,19 +299,14 @@ pub(super) fn cancel_trader_order(
         "Order request submitted successfully".to_string(),
         public_key,
     );
-    let Ok(response_value) = serde_json::to_value(&response) else {
-        return Ok(format!("Invalid response").into());
-    };
-    let Ok(mut conn) = ctx.pool.get() else {
-        return Ok(format!("Database connection error").into());
-    };
+    let response_value = serde_json::to_value(&response).map_err(|_| RpcError::Serialization)?;
+    let mut conn = ctx.pool.get().map_err(RpcError::from)?;
 
-    let Ok(ord) = TraderOrder::get(&mut conn, customer_id, cancel_order.uuid.to_string()) else {
-        return Ok(format!("Order not found").into());
-    };
+    let ord = TraderOrder::get(&mut conn, customer_id, cancel_order.uuid.to_string())
+        .map_err(RpcError::order_lookup)?;
 
     if !ord.order_status.is_cancelable() {
-        return Ok(format!("Order not cancelable").into());
+        return Err(RpcError::OrderNotCancelable.into());
     }
 
     let meta = super::headers::meta_from_headers();
@@ -366,13 +336,11 @@ pub(super) fn cancel_trader_order(
         )
     };
 
-    let Ok(serialized) = serde_json::to_vec(&rpc_command) else {
-        return Ok(format!("Could not serialize order").into());
-    };
+    let serialized = serde_json::to_vec(&rpc_command).map_err(|_| RpcError::Serialization)?;
 
     let record = Record::from_key_value(&topic, record_key, serialized);
     if let Err(e) = ctx.kafka.lock().expect("Lock poisoned!").send(&record) {
-        Ok(format!("Could not send order {:?}", e).into())
+        Err(RpcError::KafkaSendFailed(format!("{:?}", e)).into())
     } else {
         Ok(response_value)
     }
@@ -430,7 +398,7 @@ pub(super) fn submit_bulk_order(
     //}
 
     //if let Err(e) = ctx.kafka.lock().expect("Lock poisoned!").send_all(&records) {
-    //    Ok(format!("Could not send order {:?}", e).into())
+    //    Err(RpcError::KafkaSendFailed(format!("{:?}", e)).into())
     //} else {
     //    Ok("OK".into())
     //}
@@ -446,9 +414,9 @@ pub(super) fn trader_order_info(
     match ctx.pool.get() {
         Ok(mut conn) => match TraderOrder::get(&mut conn, id, params.id) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+            Err(e) => Err(RpcError::order_lookup(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -459,12 +427,9 @@ pub(super) fn lend_pool_info(
     match ctx.pool.get() {
         Ok(mut conn) => match LendPool::get(&mut conn) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!(
-                "Error fetching lend pool info: {:?}",
-                e
-            ))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -478,9 +443,9 @@ pub(super) fn lend_order_info(
     match ctx.pool.get() {
         Ok(mut conn) => match LendOrder::get(&mut conn, id, params) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+            Err(e) => Err(RpcError::order_lookup(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -501,9 +466,9 @@ pub(super) fn unrealized_pnl(
     match ctx.pool.get() {
         Ok(mut conn) => match TraderOrder::unrealized_pnl(&mut conn, id, params) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Error fetching pnl: {:?}", e))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -518,9 +483,9 @@ pub(super) fn open_orders(
     match ctx.pool.get() {
         Ok(mut conn) => match TraderOrder::open_orders(&mut conn, id, pagination.limit, pagination.offset) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Error fetching pnl: {:?}", e))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -534,12 +499,9 @@ pub(super) fn order_history(
     match ctx.pool.get() {
         Ok(mut conn) => match TraderOrder::order_history(&mut conn, id, params) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!(
-                "Error fetching order history: {:?}",
-                e
-            ))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -553,12 +515,9 @@ pub(super) fn trade_volume(
     match ctx.pool.get() {
         Ok(mut conn) => match TraderOrder::order_volume(&mut conn, id, params) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!(
-                "Error fetching order volume: {:?}",
-                e
-            ))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -572,12 +531,9 @@ pub(super) fn get_funding_payment(
     match ctx.pool.get() {
         Ok(mut conn) => match FundingRate::funding_payment(&mut conn, id, params.id) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!(
-                "Error fetching funding payment: {:?}",
-                e
-            ))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -591,8 +547,8 @@ pub(super) fn last_order_detail(
     match ctx.pool.get() {
         Ok(mut conn) => match TraderOrder::last_order(&mut conn, id) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Error fetching last order: {:?}", e))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
@@ -1,6 +1,8 @@
+use super::error::RpcError;
 use super::types::RiskParams;
 use super::*;
 use crate::database::*;
+use crate::order_book::BookView;
 use bigdecimal::{BigDecimal, ToPrimitive};
 use chrono::prelude::*;
 use jsonrpsee::{core::error::Error, server::logger::Params};
@@ -20,9 +22,9 @@ pub(super) fn btc_usd_price(
     match ctx.pool.get() {
         Ok(mut conn) => match BtcUsdPrice::get(&mut conn) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -32,16 +34,16 @@ pub(super) fn historical_price(
 ) -> Result<serde_json::Value, Error> {
     let mut args = match params.parse::<HistoricalPriceArgs>() {
         Ok(args) => args,
-        Err(e) => return Err(Error::Custom(format!("Invalid argument: {:?}", e))),
+        Err(e) => return Err(RpcError::InvalidArgument(format!("{:?}", e)).into()),
     };
     args.limit = args.limit.clamp(1, super::types::MAX_HISTORICAL_LIMIT);
 
     match ctx.pool.get() {
         Ok(mut conn) => match BtcUsdPrice::get_historical(&mut conn, args) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -60,14 +62,14 @@ pub(super) fn candle_data(
     match ctx.pool.get() {
         Ok(mut conn) => {
             match BtcUsdPrice::candles(&mut conn, interval, since, Some(limit), Some(offset)) {
-                Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-                Err(e) => Err(Error::Custom(format!(
-                    "Error fetching candles info: {:?}",
-                    e
-                ))),
+                Ok(mut o) => {
+                    ctx.candles.overlay(interval, &mut o, since, limit, offset);
+                    Ok(serde_json::to_value(o).expect("Error converting response"))
+                }
+                Err(e) => Err(RpcError::from(e).into()),
             }
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -81,9 +83,9 @@ pub(super) fn historical_funding_rate(
     match ctx.pool.get() {
         Ok(mut conn) => match FundingRate::get_historical(&mut conn, args) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -94,9 +96,9 @@ pub(super) fn get_funding_rate(
     match ctx.pool.get() {
         Ok(mut conn) => match FundingRate::get(&mut conn) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 pub(super) fn historical_fee_rate(
@@ -109,9 +111,9 @@ pub(super) fn historical_fee_rate(
     match ctx.pool.get() {
         Ok(mut conn) => match FeeHistory::get_historical(&mut conn, args) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -122,21 +124,25 @@ pub(super) fn get_fee_rate(
     match ctx.pool.get() {
         Ok(mut conn) => match FeeHistory::get(&mut conn) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
 pub(super) fn open_limit_orders(
-    _: Params<'_>,
+    params: Params<'_>,
     ctx: &RelayerContext,
 ) -> Result<serde_json::Value, Error> {
-    let Ok(mut conn) = ctx.client.get_connection() else {
-        return Ok("Redis connection error.".into());
+    let args = match params.parse::<Option<OrderBookArgs>>() {
+        Ok(args) => args.unwrap_or_default(),
+        Err(e) => return Err(RpcError::InvalidArgument(format!("{:?}", e)).into()),
     };
+    let view = BookView::new(args, super::util::BOOK_LIMIT).map_err(RpcError::from)?;
 
-    let book = order_book(&mut conn);
+    let mut conn = ctx.client.get_connection().map_err(RpcError::from)?;
+
+    let book = order_book(&mut conn, view).map_err(RpcError::from)?;
 
     Ok(serde_json::to_value(book).expect("Failed to serialize order book"))
 }
@@ -145,9 +151,7 @@ pub(super) fn recent_trade_orders(
     _: Params<'_>,
     ctx: &RelayerContext,
 ) -> Result<serde_json::Value, Error> {
-    let Ok(mut conn) = ctx.client.get_connection() else {
-        return Ok("Redis connection error.".into());
-    };
+    let mut conn = ctx.client.get_connection().map_err(RpcError::from)?;
 
     let orders = recent_orders(&mut conn);
 
@@ -161,12 +165,9 @@ pub(super) fn position_size(
     match ctx.pool.get() {
         Ok(mut conn) => match PositionSizeLog::get_latest(&mut conn) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!(
-                "Error fetching position size: {:?}",
-                e
-            ))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -179,12 +180,9 @@ pub(super) fn transaction_hashes(
     match ctx.pool.get() {
         Ok(mut conn) => match TxHash::get(&mut conn, args) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!(
-                "Error fetching transaction hashes: {:?}",
-                e
-            ))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -198,26 +196,26 @@ pub(super) fn trader_order_info(
 ) -> Result<serde_json::Value, Error> {
     let Order { data } = params.parse()?;
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
     // println!("bytes:{:?}", bytes);
     let Ok(tx) = bincode::deserialize::<relayer::QueryTraderOrderZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
     if let Err(arg) = verify_query_order(
         tx.msg.clone(),
         &bincode::serialize(&tx.query_trader_order).unwrap(),
     ) {
-        return Ok(format!("Invalid order params:{:?}", arg).into());
+        return Err(RpcError::VerificationFailed(format!("{:?}", arg)).into());
     }
     match ctx.pool.get() {
         Ok(mut conn) => {
             match TraderOrder::get_by_signature(&mut conn, tx.query_trader_order.account_id) {
                 Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-                Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+                Err(e) => Err(RpcError::order_lookup(e).into()),
             }
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -237,16 +235,16 @@ pub(super) fn trader_order_info_v1(
 ) -> Result<serde_json::Value, Error> {
     let Order { data } = params.parse()?;
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
     let Ok(tx) = bincode::deserialize::<relayer::QueryTraderOrderZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
     if let Err(arg) = verify_query_order(
         tx.msg.clone(),
         &bincode::serialize(&tx.query_trader_order).unwrap(),
     ) {
-        return Ok(format!("Invalid order params:{:?}", arg).into());
+        return Err(RpcError::VerificationFailed(format!("{:?}", arg)).into());
     }
     match ctx.pool.get() {
         Ok(mut conn) => {
@@ -287,10 +285,10 @@ pub(super) fn trader_order_info_v1(
                     };
                     Ok(serde_json::to_value(response).expect("Error converting response"))
                 }
-                Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+                Err(e) => Err(RpcError::order_lookup(e).into()),
             }
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -309,16 +307,16 @@ pub(super) fn order_funding_history(
 ) -> Result<serde_json::Value, Error> {
     let Order { data } = params.parse()?;
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
     let Ok(tx) = bincode::deserialize::<relayer::QueryTraderOrderZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
     if let Err(arg) = verify_query_order(
         tx.msg.clone(),
         &bincode::serialize(&tx.query_trader_order).unwrap(),
     ) {
-        return Ok(format!("Invalid order params:{:?}", arg).into());
+        return Err(RpcError::VerificationFailed(format!("{:?}", arg)).into());
     }
     match ctx.pool.get() {
         Ok(mut conn) => {
@@ -353,10 +351,10 @@ pub(super) fn order_funding_history(
 
                     Ok(serde_json::to_value(entries).expect("Error converting response"))
                 }
-                Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+                Err(e) => Err(RpcError::order_lookup(e).into()),
             }
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -366,26 +364,26 @@ pub(super) fn lend_order_info(
 ) -> Result<serde_json::Value, Error> {
     let Order { data } = params.parse()?;
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
     // println!("bytes:{:?}", bytes);
     let Ok(tx) = bincode::deserialize::<relayer::QueryLendOrderZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
     if let Err(arg) = verify_query_order(
         tx.msg.clone(),
         &bincode::serialize(&tx.query_lend_order).unwrap(),
     ) {
-        return Ok(format!("Invalid order params:{:?}", arg).into());
+        return Err(RpcError::VerificationFailed(format!("{:?}", arg)).into());
     }
     match ctx.pool.get() {
         Ok(mut conn) => {
             match LendOrder::get_by_signature(&mut conn, tx.query_lend_order.account_id) {
                 Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-                Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+                Err(e) => Err(RpcError::order_lookup(e).into()),
             }
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 #[derive(Serialize)]
@@ -408,16 +406,16 @@ pub(super) fn lend_order_info_v1(
 ) -> Result<serde_json::Value, Error> {
     let Order { data } = params.parse()?;
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
     let Ok(tx) = bincode::deserialize::<relayer::QueryLendOrderZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
     if let Err(arg) = verify_query_order(
         tx.msg.clone(),
         &bincode::serialize(&tx.query_lend_order).unwrap(),
     ) {
-        return Ok(format!("Invalid order params:{:?}", arg).into());
+        return Err(RpcError::VerificationFailed(format!("{:?}", arg)).into());
     }
     match ctx.pool.get() {
         Ok(mut conn) => {
@@ -431,9 +429,7 @@ pub(super) fn lend_order_info_v1(
                     let u_pnl = if order.order_status == OrderStatus::SETTLED {
                         order.payment.to_f64().unwrap_or(0.0).round()
                     } else {
-                        let pool = LendPool::get(&mut conn).map_err(|e| {
-                            Error::Custom(format!("Error fetching lend pool: {:?}", e))
-                        })?;
+                        let pool = LendPool::get(&mut conn).map_err(RpcError::from)?;
                         let tlv = pool.get_total_locked_value();
                         let tps = pool.get_total_pool_shares();
                         let npoolshare = order.npoolshare.to_f64().unwrap_or(0.0);
@@ -459,10 +455,10 @@ pub(super) fn lend_order_info_v1(
                     };
                     Ok(serde_json::to_value(response).expect("Error converting response"))
                 }
-                Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+                Err(e) => Err(RpcError::order_lookup(e).into()),
             }
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -472,17 +468,17 @@ pub(super) fn historical_trader_order_info(
 ) -> Result<serde_json::Value, Error> {
     let Order { data } = params.parse()?;
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
     // println!("bytes:{:?}", bytes);
     let Ok(tx) = bincode::deserialize::<relayer::QueryTraderOrderZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
     if let Err(arg) = verify_query_order(
         tx.msg.clone(),
         &bincode::serialize(&tx.query_trader_order).unwrap(),
     ) {
-        return Ok(format!("Invalid order params:{:?}", arg).into());
+        return Err(RpcError::VerificationFailed(format!("{:?}", arg)).into());
     }
     match ctx.pool.get() {
         Ok(mut conn) => {
@@ -491,10 +487,10 @@ pub(super) fn historical_trader_order_info(
                 tx.query_trader_order.account_id,
             ) {
                 Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-                Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+                Err(e) => Err(RpcError::from(e).into()),
             }
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -504,27 +500,27 @@ pub(super) fn historical_lend_order_info(
 ) -> Result<serde_json::Value, Error> {
     let Order { data } = params.parse()?;
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
     // println!("bytes:{:?}", bytes);
     let Ok(tx) = bincode::deserialize::<relayer::QueryLendOrderZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
     if let Err(arg) = verify_query_order(
         tx.msg.clone(),
         &bincode::serialize(&tx.query_lend_order).unwrap(),
     ) {
-        return Ok(format!("Invalid order params:{:?}", arg).into());
+        return Err(RpcError::VerificationFailed(format!("{:?}", arg)).into());
     }
     match ctx.pool.get() {
         Ok(mut conn) => {
             match LendOrder::historical_get_by_signature(&mut conn, tx.query_lend_order.account_id)
             {
                 Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-                Err(e) => Err(Error::Custom(format!("Error fetching order info: {:?}", e))),
+                Err(e) => Err(RpcError::from(e).into()),
             }
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -536,19 +532,19 @@ pub(super) fn submit_trade_order(
     let Order { data } = params.parse()?;
 
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
 
     let Ok(tx) = bincode::deserialize::<relayer::CreateTraderOrderClientZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
 
-    if let Err(_) = verify_client_create_trader_order(&tx.tx) {
-        return Ok(format!("Invalid order params").into());
+    if let Err(e) = verify_client_create_trader_order(&tx.tx) {
+        return Err(RpcError::VerificationFailed(format!("{:?}", e)).into());
     }
 
     let Ok(transaction_ser) = bincode::serialize(&tx.tx) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::Serialization.into());
     };
 
     let mut order = tx.create_trader_order.clone();
@@ -559,9 +555,7 @@ pub(super) fn submit_trade_order(
         "Order request submitted successfully".to_string(),
         public_key,
     );
-    let Ok(response_value) = serde_json::to_value(&response) else {
-        return Ok(format!("Invalid response").into());
-    };
+    let response_value = serde_json::to_value(&response).map_err(|_| RpcError::Serialization)?;
 
     let order = relayer::RpcCommand::CreateTraderOrder(
         order.clone(),
@@ -569,13 +563,11 @@ pub(super) fn submit_trade_order(
         hex::encode(transaction_ser),
         response.get_id(),
     );
-    let Ok(serialized) = serde_json::to_vec(&order) else {
-        return Ok(format!("Could not serialize order").into());
-    };
+    let serialized = serde_json::to_vec(&order).map_err(|_| RpcError::Serialization)?;
 
     let record = Record::from_key_value(&topic, "CreateTraderOrder", serialized);
     if let Err(e) = ctx.kafka.lock().expect("Lock poisoned!").send(&record) {
-        Ok(format!("Could not send order {:?}", e).into())
+        Err(RpcError::KafkaSendFailed(format!("{:?}", e)).into())
     } else {
         Ok(response_value)
     }
@@ -589,15 +581,15 @@ pub(super) fn submit_lend_order(
     let Order { data } = params.parse()?;
 
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
 
     let Ok(tx) = bincode::deserialize::<relayer::CreateLendOrderZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
 
-    if let Err(_) = verify_trade_lend_order(&tx.input) {
-        return Ok(format!("Invalid order params").into());
+    if let Err(e) = verify_trade_lend_order(&tx.input) {
+        return Err(RpcError::VerificationFailed(format!("{:?}", e)).into());
     }
 
     let mut order = tx.create_lend_order.clone();
@@ -608,22 +600,18 @@ pub(super) fn submit_lend_order(
         "Order request submitted successfully".to_string(),
         public_key,
     );
-    let Ok(response_value) = serde_json::to_value(&response) else {
-        return Ok(format!("Invalid response").into());
-    };
+    let response_value = serde_json::to_value(&response).map_err(|_| RpcError::Serialization)?;
     let order = relayer::RpcCommand::CreateLendOrder(
         order.clone(),
         meta,
         tx.input.encode_as_hex_string(),
         response.get_id(),
     );
-    let Ok(serialized) = serde_json::to_vec(&order) else {
-        return Ok(format!("Could not serialize order").into());
-    };
+    let serialized = serde_json::to_vec(&order).map_err(|_| RpcError::Serialization)?;
 
     let record = Record::from_key_value(&topic, "CreateLendOrder", serialized);
     if let Err(e) = ctx.kafka.lock().expect("Lock poisoned!").send(&record) {
-        Ok(format!("Could not send order {:?}", e).into())
+        Err(RpcError::KafkaSendFailed(format!("{:?}", e)).into())
     } else {
         Ok(response_value)
     }
@@ -637,7 +625,7 @@ pub(super) fn settle_trade_order(
     let Order { data } = params.parse()?;
 
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
 
     // Try deserializing as SlTp variant first, then fall back to plain variant
@@ -647,11 +635,11 @@ pub(super) fn settle_trade_order(
         } else if let Ok(tx) = bincode::deserialize::<relayer::ExecuteTraderOrderZkos>(&bytes) {
             (tx.execute_trader_order, tx.msg, None)
         } else {
-            return Ok(format!("Invalid bincode").into());
+            return Err(RpcError::InvalidBincode.into());
         };
 
-    if let Err(_) = verify_settle_requests(&msg) {
-        return Ok(format!("Invalid order params").into());
+    if let Err(e) = verify_settle_requests(&msg) {
+        return Err(RpcError::VerificationFailed(format!("{:?}", e)).into());
     }
 
     let public_key = execute_order.account_id.clone();
@@ -659,19 +647,14 @@ pub(super) fn settle_trade_order(
         "Order request submitted successfully".to_string(),
         public_key,
     );
-    let Ok(response_value) = serde_json::to_value(&response) else {
-        return Ok(format!("Invalid response").into());
-    };
-    let Ok(mut conn) = ctx.pool.get() else {
-        return Ok(format!("Database connection error").into());
-    };
+    let response_value = serde_json::to_value(&response).map_err(|_| RpcError::Serialization)?;
+    let mut conn = ctx.pool.get().map_err(RpcError::from)?;
 
-    let Ok(ord) = TraderOrder::get_by_uuid(&mut conn, execute_order.uuid.to_string()) else {
-        return Ok(format!("Order not found").into());
-    };
+    let ord = TraderOrder::get_by_uuid(&mut conn, execute_order.uuid.to_string())
+        .map_err(RpcError::order_lookup)?;
 
     if !ord.order_status.is_closed() {
-        return Ok(format!("Order closed").into());
+        return Err(RpcError::OrderClosed.into());
     }
 
     let meta = super::headers::meta_from_headers();
@@ -701,13 +684,11 @@ pub(super) fn settle_trade_order(
         )
     };
 
-    let Ok(serialized) = serde_json::to_vec(&rpc_command) else {
-        return Ok(format!("Could not serialize order").into());
-    };
+    let serialized = serde_json::to_vec(&rpc_command).map_err(|_| RpcError::Serialization)?;
 
     let record = Record::from_key_value(&topic, record_key, serialized);
     if let Err(e) = ctx.kafka.lock().expect("Lock poisoned!").send(&record) {
-        Ok(format!("Could not send order {:?}", e).into())
+        Err(RpcError::KafkaSendFailed(format!("{:?}", e)).into())
     } else {
         Ok(response_value)
     }
@@ -721,15 +702,15 @@ pub(super) fn settle_lend_order(
     let Order { data } = params.parse()?;
 
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
 
     let Ok(tx) = bincode::deserialize::<relayer::ExecuteLendOrderZkos>(&bytes) else {
-        return Ok(format!("Invalid bincode").into());
+        return Err(RpcError::InvalidBincode.into());
     };
 
-    if let Err(_) = verify_settle_requests(&tx.msg) {
-        return Ok(format!("Invalid order params").into());
+    if let Err(e) = verify_settle_requests(&tx.msg) {
+        return Err(RpcError::VerificationFailed(format!("{:?}", e)).into());
     }
 
     let order = tx.execute_lend_order.clone();
@@ -738,19 +719,14 @@ pub(super) fn settle_lend_order(
         "Order request submitted successfully".to_string(),
         public_key,
     );
-    let Ok(response_value) = serde_json::to_value(&response) else {
-        return Ok(format!("Invalid response").into());
-    };
-    let Ok(mut conn) = ctx.pool.get() else {
-        return Ok(format!("Database connection error").into());
-    };
+    let response_value = serde_json::to_value(&response).map_err(|_| RpcError::Serialization)?;
+    let mut conn = ctx.pool.get().map_err(RpcError::from)?;
 
-    let Ok(ord) = LendOrder::get_by_uuid(&mut conn, order.uuid.to_string()) else {
-        return Ok(format!("Order not found").into());
-    };
+    let ord = LendOrder::get_by_uuid(&mut conn, order.uuid.to_string())
+        .map_err(RpcError::order_lookup)?;
 
     if !ord.order_status.is_closed() {
-        return Ok(format!("Order closed").into());
+        return Err(RpcError::OrderClosed.into());
     }
 
     let meta = super::headers::meta_from_headers();
@@ -761,13 +737,11 @@ pub(super) fn settle_lend_order(
         tx.msg.encode_as_hex_string(),
         response.get_id(),
     );
-    let Ok(serialized) = serde_json::to_vec(&order) else {
-        return Ok(format!("Could not serialize order").into());
-    };
+    let serialized = serde_json::to_vec(&order).map_err(|_| RpcError::Serialization)?;
 
     let record = Record::from_key_value(&topic, "ExecuteLendOrder", serialized);
     if let Err(e) = ctx.kafka.lock().expect("Lock poisoned!").send(&record) {
-        Ok(format!("Could not send order {:?}", e).into())
+        Err(RpcError::KafkaSendFailed(format!("{:?}", e)).into())
     } else {
         Ok(response_value)
     }
@@ -781,7 +755,7 @@ pub(super) fn cancel_trader_order(
     let Order { data } = params.parse()?;
 
     let Ok(bytes) = hex::decode(&data) else {
-        return Ok(format!("Invalid hex data").into());
+        return Err(RpcError::InvalidHex.into());
     };
 
     // Try deserializing as SlTp variant first, then fall back to plain variant
@@ -791,14 +765,14 @@ pub(super) fn cancel_trader_order(
         } else if let Ok(tx) = bincode::deserialize::<relayer::CancelTraderOrderZkos>(&bytes) {
             (tx.cancel_trader_order, tx.msg, None)
         } else {
-            return Ok(format!("Invalid bincode").into());
+            return Err(RpcError::InvalidBincode.into());
         };
 
-    if let Err(_) = verify_query_order(
+    if let Err(e) = verify_query_order(
         msg.convert_cancel_to_query(),
         &bincode::serialize(&cancel_order).unwrap(),
     ) {
-        return Ok(format!("Invalid order params").into());
+        return Err(RpcError::VerificationFailed(format!("{:?}", e)).into());
     }
 
     let public_key = cancel_order.account_id.clone();
@@ -806,19 +780,14 @@ pub(super) fn cancel_trader_order(
         "Order request submitted successfully".to_string(),
         public_key,
     );
-    let Ok(response_value) = serde_json::to_value(&response) else {
-        return Ok(format!("Invalid response").into());
-    };
-    let Ok(mut conn) = ctx.pool.get() else {
-        return Ok(format!("Database connection error").into());
-    };
+    let response_value = serde_json::to_value(&response).map_err(|_| RpcError::Serialization)?;
+    let mut conn = ctx.pool.get().map_err(RpcError::from)?;
 
-    let Ok(ord) = TraderOrder::get_by_uuid(&mut conn, cancel_order.uuid.to_string()) else {
-        return Ok(format!("Order not found").into());
-    };
+    let ord = TraderOrder::get_by_uuid(&mut conn, cancel_order.uuid.to_string())
+        .map_err(RpcError::order_lookup)?;
 
     if !ord.order_status.is_cancelable() {
-        return Ok(format!("Order not cancelable").into());
+        return Err(RpcError::OrderNotCancelable.into());
     }
 
     let meta = super::headers::meta_from_headers();
@@ -848,13 +817,11 @@ pub(super) fn cancel_trader_order(
         )
     };
 
-    let Ok(serialized) = serde_json::to_vec(&rpc_command) else {
-        return Ok(format!("Could not serialize order").into());
-    };
+    let serialized = serde_json::to_vec(&rpc_command).map_err(|_| RpcError::Serialization)?;
 
     let record = Record::from_key_value(&topic, record_key, serialized);
     if let Err(e) = ctx.kafka.lock().expect("Lock poisoned!").send(&record) {
-        Ok(format!("Could not send order {:?}", e).into())
+        Err(RpcError::KafkaSendFailed(format!("{:?}", e)).into())
     } else {
         Ok(response_value)
     }
@@ -870,12 +837,9 @@ pub(super) fn pool_share_value(
                 let value = o.get_pool_share_value();
                 Ok(serde_json::to_value(value).expect("Error converting response"))
             }
-            Err(e) => Err(Error::Custom(format!(
-                "Error fetching lend pool info: {:?}",
-                e
-            ))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -886,12 +850,9 @@ pub(super) fn lend_pool_info(
     match ctx.pool.get() {
         Ok(mut conn) => match LendPool::get(&mut conn) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!(
-                "Error fetching lend pool info: {:?}",
-                e
-            ))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -902,9 +863,9 @@ pub(super) fn last_day_apy(
     match ctx.pool.get() {
         Ok(mut conn) => match PoolAnalytics::last_day_apy_now(&mut conn) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 pub(super) fn apy_chart(
@@ -916,11 +877,11 @@ pub(super) fn apy_chart(
                              // use crate::database::models::PoolAnalytics; // adjust path if needed
     let args: crate::rpc::types::ApySeriesArgs = params
         .parse()
-        .map_err(|e| Error::Custom(format!("Invalid argument: {:?}", e)))?;
+        .map_err(|e| RpcError::InvalidArgument(format!("{:?}", e)))?;
 
     let (window, step, lookback) = match args.resolve() {
         Ok(t) => t,
-        Err(msg) => return Err(Error::Custom(msg)),
+        Err(msg) => return Err(RpcError::InvalidArgument(msg).into()),
     };
 
     match ctx.pool.get() {
@@ -938,11 +899,11 @@ pub(super) fn apy_chart(
                 .bind::<diesel::sql_types::Text, _>(step)
                 .bind::<diesel::sql_types::Text, _>(lookback)
                 .load(&mut conn)
-                .map_err(|e| Error::Custom(format!("Database error: {:?}", e)))?;
+                .map_err(RpcError::from)?;
 
             Ok(serde_json::to_value(rows).expect("Error converting response"))
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -953,9 +914,9 @@ pub(super) fn open_interest(
     match ctx.pool.get() {
         Ok(mut conn) => match get_open_interest(&mut conn) {
             Ok(o) => Ok(serde_json::to_value(o).expect("Error converting response")),
-            Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+            Err(e) => Err(RpcError::from(e).into()),
         },
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -967,11 +928,11 @@ pub(super) fn open_interest_chart(
 
     let args: crate::rpc::types::OiChartArgs = params
         .parse()
-        .map_err(|e| Error::Custom(format!("Invalid argument: {:?}", e)))?;
+        .map_err(|e| RpcError::InvalidArgument(format!("{:?}", e)))?;
 
     let (window, step) = match args.resolve() {
         Ok(t) => t,
-        Err(msg) => return Err(Error::Custom(msg)),
+        Err(msg) => return Err(RpcError::InvalidArgument(msg).into()),
     };
 
     match ctx.pool.get() {
@@ -985,11 +946,11 @@ pub(super) fn open_interest_chart(
                 .bind::<diesel::sql_types::Text, _>(window)
                 .bind::<diesel::sql_types::Text, _>(step)
                 .load(&mut conn)
-                .map_err(|e| Error::Custom(format!("Database error: {:?}", e)))?;
+                .map_err(RpcError::from)?;
 
             Ok(serde_json::to_value(rows).expect("Error converting response"))
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 // pub(super) fn account_summary_by_twilight_address(
@@ -1014,12 +975,12 @@ pub(super) fn account_summary_by_twilight_address(
 ) -> Result<serde_json::Value, Error> {
     let args: crate::rpc::types::AccountSummaryByTAddressArgs = params.parse()?;
 
-    let (t_address, from, to) = args.normalize().map_err(Error::Custom)?;
+    let (t_address, from, to) = args.normalize().map_err(RpcError::InvalidArgument)?;
 
     match ctx.pool.get() {
         Ok(mut conn) => {
             let summary = account_summary_by_twilight_address_fn(&mut conn, &t_address, from, to)
-                .map_err(|e| Error::Custom(format!("Database error: {:?}", e)))?;
+                .map_err(RpcError::from)?;
 
             let response = crate::rpc::types::AccountSummaryByTAddressResponse {
                 from,
@@ -1034,7 +995,7 @@ pub(super) fn account_summary_by_twilight_address(
 
             Ok(serde_json::to_value(response).expect("Error converting response"))
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -1044,12 +1005,12 @@ pub(super) fn all_account_summaries(
 ) -> Result<serde_json::Value, Error> {
     let args: crate::rpc::types::AllAccountSummariesArgs = params.parse()?;
 
-    let (from, to, limit, offset) = args.normalize().map_err(Error::Custom)?;
+    let (from, to, limit, offset) = args.normalize().map_err(RpcError::InvalidArgument)?;
 
     match ctx.pool.get() {
         Ok(mut conn) => {
             let rows = all_account_summaries_fn(&mut conn, from, to, limit, offset)
-                .map_err(|e| Error::Custom(format!("Database error: {:?}", e)))?;
+                .map_err(RpcError::from)?;
 
             let summaries = rows
                 .into_iter()
@@ -1074,7 +1035,7 @@ pub(super) fn all_account_summaries(
 
             Ok(serde_json::to_value(response).expect("Error converting response"))
         }
-        Err(e) => Err(Error::Custom(format!("Database error: {:?}", e))),
+        Err(e) => Err(RpcError::from(e).into()),
     }
 }
 
@@ -1083,10 +1044,7 @@ pub(super) fn get_market_stats(
     ctx: &RelayerContext,
 ) -> Result<serde_json::Value, Error> {
     // 1. Read latest RiskState from Redis
-    let mut redis_conn = ctx
-        .client
-        .get_connection()
-        .map_err(|e| Error::Custom(format!("Redis connection error: {:?}", e)))?;
+    let mut redis_conn = ctx.client.get_connection().map_err(RpcError::from)?;
 
     let state_json: Option<String> = redis::cmd("GET")
         .arg("risk_state")
@@ -1110,10 +1068,7 @@ pub(super) fn get_market_stats(
     };
 
     // 2. Get pool equity from lend_pool table
-    let mut db_conn = ctx
-        .pool
-        .get()
-        .map_err(|e| Error::Custom(format!("Database error: {:?}", e)))?;
+    let mut db_conn = ctx.pool.get().map_err(RpcError::from)?;
 
     let pool_equity_btc = match LendPool::get(&mut db_conn) {
         Ok(pool) => pool.get_total_locked_value(),
@@ -1170,4 +1125,18 @@ mod tests {
         );
         assert!(verify_result.is_ok());
     }
+
+    #[test]
+    fn open_limit_levels_keep_the_order_id_field() {
+        let mut levels = crate::order_book::BookLevels::default();
+        levels.insert(crate::order_book::BookSide::Bid, 4_200_000, 3.0);
+
+        let book = super::super::types::OpenLimitOrders::from(levels.depth());
+        let book = serde_json::to_value(book).unwrap();
+        assert_eq!(
+            book["bid"][0],
+            serde_json::json!({ "id": "", "price": 42000.0, "positionsize": 3.0, "cumulative": 3.0 })
+        );
+        assert_eq!(book["ask"], serde_json::json!([]));
+    }
 }
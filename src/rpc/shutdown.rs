@@ -0,0 +1,223 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::{header, Body, Request, Response, StatusCode};
+use jsonrpsee::types::ErrorObjectOwned;
+use log::{info, warn};
+use tokio::sync::{watch, Notify};
+use tower::{Layer, Service};
+
+use super::error::RpcError;
+
+struct Inner {
+    closing: AtomicBool,
+    in_flight: AtomicUsize,
+    idle: Notify,
+    closed: watch::Sender<bool>,
+}
+
+/// Shared shutdown state for the API servers. `ShutdownLayer` keeps count of in-flight HTTP
+/// calls and refuses new ones once `close` is called, websocket subscriptions watch `closed`
+/// to send their final notification.
+#[derive(Clone)]
+pub struct Shutdown {
+    inner: Arc<Inner>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        let (closed, _) = watch::channel(false);
+
+        Shutdown {
+            inner: Arc::new(Inner {
+                closing: AtomicBool::new(false),
+                in_flight: AtomicUsize::new(0),
+                idle: Notify::new(),
+                closed,
+            }),
+        }
+    }
+}
+
+impl Shutdown {
+    pub fn layer(&self) -> ShutdownLayer {
+        ShutdownLayer {
+            shutdown: self.clone(),
+        }
+    }
+
+    pub fn is_closing(&self) -> bool {
+        self.inner.closing.load(Ordering::SeqCst)
+    }
+
+    /// Refuse new calls and notify every subscription.
+    pub fn close(&self) {
+        self.inner.closing.store(true, Ordering::SeqCst);
+        self.inner.closed.send_replace(true);
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<bool> {
+        self.inner.closed.subscribe()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.inner.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Wait until every in-flight call has returned, or `timeout` has passed. Returns whether
+    /// all calls completed.
+    pub async fn drained(&self, timeout: Duration) -> bool {
+        let wait = async {
+            loop {
+                let idle = self.inner.idle.notified();
+                if self.in_flight() == 0 {
+                    return;
+                }
+                info!("Waiting for {} in-flight calls", self.in_flight());
+                idle.await;
+            }
+        };
+
+        if tokio::time::timeout(timeout, wait).await.is_err() {
+            warn!(
+                "{} calls still in flight after {:?}",
+                self.in_flight(),
+                timeout
+            );
+            return false;
+        }
+
+        true
+    }
+}
+
+/// Decrements the in-flight count when a call completes or is dropped.
+struct InFlight(Shutdown);
+
+impl InFlight {
+    fn new(shutdown: Shutdown) -> InFlight {
+        shutdown.inner.in_flight.fetch_add(1, Ordering::SeqCst);
+        InFlight(shutdown)
+    }
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        if self.0.inner.in_flight.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.inner.idle.notify_waiters();
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ShutdownLayer {
+    shutdown: Shutdown,
+}
+
+impl<S> Layer<S> for ShutdownLayer {
+    type Service = ShutdownService<S>;
+    fn layer(&self, inner: S) -> Self::Service {
+        ShutdownService {
+            inner,
+            shutdown: self.shutdown.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct ShutdownService<S> {
+    inner: S,
+    shutdown: Shutdown,
+}
+
+impl<S> Service<Request<Body>> for ShutdownService<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Send,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if self.shutdown.is_closing() {
+            let body = serde_json::json!({
+                "jsonrpc": "2.0",
+                "error": ErrorObjectOwned::from(RpcError::ShuttingDown),
+                "id": null,
+            });
+            let response = Response::builder()
+                .status(StatusCode::SERVICE_UNAVAILABLE)
+                .header(header::CONTENT_TYPE, "application/json")
+                .header(header::CONNECTION, "close")
+                .body(Body::from(body.to_string()))
+                .expect("valid response");
+            return Box::pin(async move { Ok(response) });
+        }
+
+        let guard = InFlight::new(self.shutdown.clone());
+        let mut inner = self.inner.clone();
+        Box::pin(async move {
+            let response = inner.call(req).await;
+            drop(guard);
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::Infallible;
+    use tower::{service_fn, ServiceExt};
+
+    #[tokio::test]
+    async fn drains_in_flight_calls_and_refuses_new_ones() {
+        let shutdown = Shutdown::default();
+        let (release_tx, release_rx) = tokio::sync::oneshot::channel::<()>();
+        let release_rx = Arc::new(tokio::sync::Mutex::new(Some(release_rx)));
+
+        let service = shutdown
+            .layer()
+            .layer(service_fn(move |_req: Request<Body>| {
+                let release_rx = release_rx.clone();
+                async move {
+                    if let Some(rx) = release_rx.lock().await.take() {
+                        let _ = rx.await;
+                    }
+                    Ok::<_, Infallible>(Response::new(Body::from("done")))
+                }
+            }));
+
+        let pending = tokio::spawn(service.clone().oneshot(Request::new(Body::empty())));
+        while shutdown.in_flight() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        shutdown.close();
+        assert!(*shutdown.subscribe().borrow());
+
+        let refused = service
+            .clone()
+            .oneshot(Request::new(Body::empty()))
+            .await
+            .unwrap();
+        assert_eq!(refused.status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        assert!(!shutdown.drained(Duration::from_millis(50)).await);
+        release_tx.send(()).unwrap();
+        assert!(shutdown.drained(Duration::from_secs(5)).await);
+
+        let response = pending.await.unwrap().unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+}
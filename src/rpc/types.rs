@@ -39,6 +39,8 @@ impl Default for PaginationParams {
 // •	Fee History
 use crate::auth::UserInfo;
 use crate::database::OrderStatus;
+use crate::order_book::{BookDepth, Level};
+pub use crate::order_book::{OrderBookArgs, MAX_BOOK_DEPTH};
 use chrono::{prelude::*, Duration};
 use serde::{Deserialize, Serialize};
 
@@ -51,7 +53,7 @@ pub struct RpcArgs<T> {
 impl<T> RpcArgs<T> {
     pub fn unpack(self) -> (i64, T) {
         let RpcArgs {
-            user: UserInfo { customer_id },
+            user: UserInfo { customer_id, .. },
             params,
         } = self;
         (customer_id, params)
@@ -168,6 +170,41 @@ pub struct Ask {
     pub price: f64,
 }
 
+/// A level of `open_limit_orders`. The book is aggregated by price, so `id` is always empty; it
+/// is kept for clients of the earlier per-order response.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpenLimitLevel {
+    pub id: String,
+    #[serde(flatten)]
+    pub level: Level,
+}
+
+/// The `open_limit_orders` response, best price first on both sides.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OpenLimitOrders {
+    pub bid: Vec<OpenLimitLevel>,
+    pub ask: Vec<OpenLimitLevel>,
+}
+
+impl From<BookDepth> for OpenLimitOrders {
+    fn from(depth: BookDepth) -> Self {
+        let levels = |levels: Vec<Level>| {
+            levels
+                .into_iter()
+                .map(|level| OpenLimitLevel {
+                    id: String::new(),
+                    level,
+                })
+                .collect()
+        };
+
+        OpenLimitOrders {
+            bid: levels(depth.bid),
+            ask: levels(depth.ask),
+        }
+    }
+}
+
 //******Open Limit Orders end */
 //***** Recent Trade Orders (24Hours) */
 #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
@@ -276,6 +313,37 @@ pub enum Interval {
 }
 
 impl Interval {
+    /// Intervals with their own rows in the `candles` table, each after the one it is rolled up
+    /// from. `ONE_DAY_CHANGE` is rolled up from the hourly rows when read.
+    pub const MATERIALIZED: [Interval; 9] = [
+        Interval::ONE_MINUTE,
+        Interval::FIVE_MINUTE,
+        Interval::FIFTEEN_MINUTE,
+        Interval::THIRTY_MINUTE,
+        Interval::ONE_HOUR,
+        Interval::FOUR_HOUR,
+        Interval::EIGHT_HOUR,
+        Interval::TWELVE_HOUR,
+        Interval::ONE_DAY,
+    ];
+
+    /// The materialized interval this one is rolled up from, `None` for `ONE_MINUTE` which is
+    /// built from the raw price and trade history.
+    pub fn rollup_source(&self) -> Option<Interval> {
+        match self {
+            Interval::ONE_MINUTE => None,
+            Interval::FIVE_MINUTE
+            | Interval::FIFTEEN_MINUTE
+            | Interval::THIRTY_MINUTE
+            | Interval::ONE_HOUR => Some(Interval::ONE_MINUTE),
+            Interval::FOUR_HOUR
+            | Interval::EIGHT_HOUR
+            | Interval::TWELVE_HOUR
+            | Interval::ONE_DAY
+            | Interval::ONE_DAY_CHANGE => Some(Interval::ONE_HOUR),
+        }
+    }
+
     pub fn duration(&self) -> Duration {
         match self {
             Interval::ONE_MINUTE => Duration::minutes(1),
@@ -291,20 +359,20 @@ impl Interval {
         }
     }
 
-    pub fn interval_sql(&self) -> String {
+    /// Postgres interval text, bind it and cast with `$n::interval`.
+    pub fn interval_sql(&self) -> &'static str {
         match self {
-            Interval::ONE_MINUTE => "'1 minute'",
-            Interval::FIVE_MINUTE => "'5 minutes'",
-            Interval::FIFTEEN_MINUTE => "'15 minutes'",
-            Interval::THIRTY_MINUTE => "'30 minutes'",
-            Interval::ONE_HOUR => "'1 hour'",
-            Interval::FOUR_HOUR => "'4 hours'",
-            Interval::EIGHT_HOUR => "'8 hours'",
-            Interval::TWELVE_HOUR => "'12 hours'",
-            Interval::ONE_DAY => "'1 day'",
-            Interval::ONE_DAY_CHANGE => "'1 day'",
+            Interval::ONE_MINUTE => "1 minute",
+            Interval::FIVE_MINUTE => "5 minutes",
+            Interval::FIFTEEN_MINUTE => "15 minutes",
+            Interval::THIRTY_MINUTE => "30 minutes",
+            Interval::ONE_HOUR => "1 hour",
+            Interval::FOUR_HOUR => "4 hours",
+            Interval::EIGHT_HOUR => "8 hours",
+            Interval::TWELVE_HOUR => "12 hours",
+            Interval::ONE_DAY => "1 day",
+            Interval::ONE_DAY_CHANGE => "1 day",
         }
-        .into()
     }
 }
 
@@ -808,3 +876,32 @@ pub struct MarketRiskStatsResponse {
     pub params: RiskParams,
     pub funding_rate: FundingRateResponse,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadLetterArgs {
+    #[serde(default)]
+    pub include_replayed: bool,
+    #[serde(default = "default_page_limit")]
+    pub limit: i64,
+    #[serde(default)]
+    pub offset: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReplayDeadLetterArgs {
+    pub ids: Vec<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ReconcileOrderBookArgs {
+    /// Rewrite redis from postgres when they disagree, otherwise only report.
+    #[serde(default)]
+    pub repair: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadLetterList {
+    /// Dead letters not replayed yet, across all pages.
+    pub pending: i64,
+    pub events: Vec<crate::database::DeadLetterEvent>,
+}
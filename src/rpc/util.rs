@@ -1,62 +1,19 @@
-use crate::database::{Ask, Bid, OrderBook, RecentOrder};
+use crate::database::RecentOrder;
+use crate::order_book::{BookLevels, BookView};
 use super::types::{FundingRateResponse, MarketRiskStatsResponse, MarketStatus, RiskParams};
 use chrono::{DateTime, TimeDelta, Utc};
-use itertools::Itertools;
 use relayer_core::relayer::RiskState;
 
-const BOOK_LIMIT: usize = 10;
+/// Levels per side `open_limit_orders` returns when no `depth` is given.
+pub(super) const BOOK_LIMIT: usize = 10;
 const RECENT_ORDER_LIMIT: usize = 25;
 
-pub fn order_book(conn: &mut redis::Connection) -> OrderBook {
-    let asks: redis::Iter<f64> = redis::cmd("ZSCAN")
-        .arg("ask")
-        .cursor_arg(0)
-        .clone()
-        .iter(conn)
-        .unwrap();
-
-    let ask: Vec<_> = asks
-        .chunks(2)
-        .into_iter()
-        .take(BOOK_LIMIT)
-        .map(|mut chunk| {
-            let positionsize = chunk.next().unwrap();
-            let price = chunk.next().unwrap() / 100.0;
-
-            Ask {
-                id: "".into(),
-                positionsize,
-                price,
-            }
-        })
-        .collect();
-
-    let bids: redis::Iter<f64> = redis::cmd("ZSCAN")
-        .arg("bid")
-        .cursor_arg(0)
-        .clone()
-        .iter(conn)
-        .unwrap();
-
-    let bids: Vec<_> = bids.collect();
-    let bid: Vec<_> = bids
-        .chunks(2)
-        .rev()
-        .into_iter()
-        .take(BOOK_LIMIT)
-        .map(|chunk| {
-            let positionsize = chunk[0];
-            let price = chunk[1] / 100.0;
-
-            Bid {
-                id: "".into(),
-                positionsize,
-                price,
-            }
-        })
-        .collect();
-
-    OrderBook { ask, bid }
+/// The aggregated book from redis as `view` sees it, best price first on both sides.
+pub fn order_book(
+    conn: &mut redis::Connection,
+    view: BookView,
+) -> redis::RedisResult<super::types::OpenLimitOrders> {
+    Ok(BookLevels::load(conn)?.view(view).depth().into())
 }
 
 pub fn recent_orders(conn: &mut redis::Connection) -> Vec<RecentOrder> {
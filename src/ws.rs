@@ -1,6 +1,7 @@
-use crate::database::{NewOrderBookOrder, TraderOrder};
-use crate::kafka::start_consumer;
-use crate::rpc::Interval;
+use crate::database::OrderStatus;
+use crate::event_source::EventSource;
+use crate::kafka::KafkaSource;
+use crate::rpc::shutdown::Shutdown;
 // use bigdecimal::ToPrimitive;
 use chrono::prelude::*;
 use crossbeam_channel::{unbounded, Sender as CrossbeamSender};
@@ -11,21 +12,19 @@ use log::{debug, error, info, trace};
 use redis::Client;
 use relayer_core::db::Event;
 use relayer_core::relayer::PositionType;
-use relayer_core::twilight_relayer_sdk::twilight_client_sdk::relayer_types::{
-    OrderStatus, OrderType,
-};
 use serde::{Deserialize, Serialize};
-use std::{
-    collections::HashMap,
-    sync::RwLock,
-    time::{Duration, Instant},
-};
+use std::time::{Duration, Instant};
 use tokio::{
     sync::broadcast::{channel, Sender},
     task::JoinHandle,
 };
 
+pub mod candles;
 mod methods;
+pub mod order_book;
+
+pub use candles::LiveCandles;
+pub use order_book::OrderBookFeed;
 
 // const SNAPSHOT_TOPIC: &str = "CoreEventLogTopic";
 // const WEBSOCKET_GROUP: &str = "Websocket";
@@ -45,35 +44,69 @@ struct RecentOrder {
     timestamp: String,
 }
 pub struct WsContext {
-    client: Client,
+    pub client: Client,
     price_feed: Sender<(f64, DateTime<Utc>)>,
-    order_book: Sender<NewOrderBookOrder>,
+    pub order_book: OrderBookFeed,
     recent_trades: Sender<RecentOrder>,
-    pub candles: RwLock<HashMap<Interval, Sender<serde_json::Value>>>,
+    pub candles: LiveCandles,
     pub pool: ManagedPool,
+    shutdown: Shutdown,
     _completions: CrossbeamSender<crate::kafka::Completion>,
     _watcher: JoinHandle<()>,
-    _kafka_sub: std::thread::JoinHandle<()>,
+    _source: std::thread::JoinHandle<()>,
+    _book_poller: std::thread::JoinHandle<()>,
 }
 
 impl WsContext {
-    pub fn with_pool(pool: ManagedPool, client: Client) -> WsContext {
+    pub fn with_pool(
+        pool: ManagedPool,
+        client: Client,
+        candles: LiveCandles,
+        shutdown: Shutdown,
+    ) -> WsContext {
         dotenv::dotenv().ok();
         let snapshot_topic =
             std::env::var("CORE_EVENT_LOG").unwrap_or("CoreEventLogTopic".to_string());
         let websocket_group =
             std::env::var("WEBSOCKET_KAFKA_GROUP").unwrap_or("Websocket".to_string());
+
+        Self::with_source(
+            pool,
+            client,
+            KafkaSource::from_env(websocket_group, snapshot_topic),
+            candles,
+            shutdown,
+        )
+    }
+
+    /// Build a context whose subscriptions are fed from `source`, e.g. a recorded event log
+    /// during local development. `candles` is kept up to date from the same events. Every
+    /// subscription is closed with a final notification and the order book poller stops once
+    /// `shutdown` is closed.
+    pub fn with_source<S: EventSource>(
+        pool: ManagedPool,
+        client: Client,
+        source: S,
+        candles: LiveCandles,
+        shutdown: Shutdown,
+    ) -> WsContext {
         let (price_feed, _) = channel::<(f64, DateTime<Utc>)>(BROADCAST_CHANNEL_CAPACITY);
-        let (order_book, _) = channel::<NewOrderBookOrder>(BROADCAST_CHANNEL_CAPACITY);
+        let order_book = OrderBookFeed::default();
+        let _book_poller = order_book.spawn(
+            client.clone(),
+            Duration::from_millis(WS_UPDATE_INTERVAL),
+            shutdown.clone(),
+        );
         let (recent_trades, _) = channel::<RecentOrder>(BROADCAST_CHANNEL_CAPACITY);
 
         let price_feed2 = price_feed.clone();
-        let order_book2 = order_book.clone();
         let recent_trades2 = recent_trades.clone();
+        let candles2 = candles.clone();
+        let seed_pool = pool.clone();
 
-        let (completions, rx, _kafka_sub) = {
+        let (completions, rx, _source) = {
             let (tx, rx) = unbounded();
-            let (completions, h) = start_consumer(websocket_group, snapshot_topic, tx);
+            let (completions, h) = source.start(tx);
 
             (completions, rx, h)
         };
@@ -81,11 +114,26 @@ impl WsContext {
         let notify = completions.clone();
 
         let _watcher = tokio::task::spawn(async move {
+            // The seed runs diesel queries, keep them off the async workers.
+            let seed_candles = candles2.clone();
+            let seeded = tokio::task::spawn_blocking(move || match seed_pool.get() {
+                Ok(mut conn) => seed_candles.seed(&mut conn).map_err(|e| format!("{:?}", e)),
+                Err(e) => Err(format!("{:?}", e)),
+            })
+            .await;
+            match seeded {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => error!("Failed to seed live candles: {}", e),
+                Err(e) => error!("Live candle seed panicked: {:?}", e),
+            }
+
             let mut deadline = Instant::now() + Duration::from_millis(WS_UPDATE_INTERVAL);
             loop {
                 match rx.recv_deadline(deadline) {
                     Ok((completion, msgs)) => {
                         for msg in msgs {
+                            // Funding updates re-send filled orders, they aren't new trades.
+                            let funding_update = matches!(msg, Event::TraderOrderFundingUpdate(..));
                             match msg {
                                 Event::FeeUpdate(cmd, event_time) => match cmd {
                                     relayer_core::relayer::RelayerCommand::UpdateFees(
@@ -109,50 +157,33 @@ impl WsContext {
                                 | Event::TraderOrderUpdate(to, ..)
                                 | Event::TraderOrderFundingUpdate(to, ..)
                                 | Event::TraderOrderLiquidation(to, ..) => {
-                                    if to.order_type == OrderType::LIMIT {
-                                        let order =
-                                            NewOrderBookOrder::new(TraderOrder::from(to.clone()));
-                                        if let Err(e) = order_book2.send(order) {
-                                            info!("No order book subscribers present {:?}", e);
-                                        }
-                                    }
+                                    // Same predicate the materialized candles filter trader_order
+                                    // rows with, so the live bar matches the stored one.
+                                    if OrderStatus::from(to.order_status.clone()).is_trade() {
+                                        let ts = DateTime::parse_from_rfc3339(&to.timestamp)
+                                            .map(|ts| ts.with_timezone(&Utc))
+                                            .unwrap_or_else(|_| Utc::now());
+                                        let recent_order = RecentOrder {
+                                            order_id: to.uuid.to_string(),
+                                            side: to.position_type.into(),
+                                            price: to.entryprice.into(),
+                                            positionsize: to.positionsize.into(),
+                                            timestamp: to.timestamp,
+                                        };
+                                        let _ = recent_trades2.send(recent_order);
 
-                                    match to.order_status {
-                                        OrderStatus::SETTLED
-                                        | OrderStatus::FILLED
-                                        | OrderStatus::LIQUIDATE => {
-                                            let recent_order = RecentOrder {
-                                                order_id: to.uuid.to_string(),
-                                                side: to.position_type.into(),
-                                                price: to.entryprice.into(),
-                                                positionsize: to.positionsize.into(),
-                                                timestamp: to.timestamp,
-                                            };
-                                            let _ = recent_trades2.send(recent_order);
+                                        if !funding_update {
+                                            candles2.trade(
+                                                to.entryprice.into(),
+                                                to.positionsize.into(),
+                                                ts,
+                                            );
                                         }
-                                        _ => {}
-                                    }
-                                }
-                                // added for limit order update for settlement order
-                                Event::TraderOrderLimitUpdate(to, cmd, _seq) => {
-                                    let settlement_price = match cmd {
-                                        relayer_core::relayer::RpcCommand::ExecuteTraderOrder(
-                                            execute_trader_order,
-                                            _meta,
-                                            _zkos_hex_string,
-                                            _request_id,
-                                        ) => execute_trader_order.execution_price,
-                                        _ => 0.0, // Default value for other command types
-                                    };
-
-                                    let order = NewOrderBookOrder::new_close_limit(
-                                        TraderOrder::from(to.clone()),
-                                        settlement_price,
-                                    );
-                                    if let Err(e) = order_book2.send(order) {
-                                        info!("No order book subscribers present {:?}", e);
                                     }
                                 }
+                                // The order book feed follows redis, where the archiver
+                                // applies these.
+                                Event::TraderOrderLimitUpdate(..) => {}
                                 Event::LendOrder(_lend_order, _cmd, _seq) => {}
                                 Event::FundingRateUpdate(
                                     _funding_rate,
@@ -160,9 +191,18 @@ impl WsContext {
                                     _system_time,
                                 ) => {}
                                 Event::CurrentPriceUpdate(current_price, system_time) => {
-                                    let ts = DateTime::parse_from_rfc3339(&system_time)
-                                        .expect("Bad datetime format")
-                                        .into();
+                                    // One bad event mustn't stop every feed this task drives.
+                                    let ts = match DateTime::parse_from_rfc3339(&system_time) {
+                                        Ok(ts) => ts.into(),
+                                        Err(e) => {
+                                            error!(
+                                                "Bad datetime {:?} on price update {}: {:?}",
+                                                system_time, current_price, e
+                                            );
+                                            continue;
+                                        }
+                                    };
+                                    candles2.price(current_price, ts);
                                     if let Err(e) = price_feed2.send((current_price, ts)) {
                                         debug!("No subscribers present {:?}", e);
                                     }
@@ -205,16 +245,23 @@ impl WsContext {
             price_feed,
             order_book,
             recent_trades,
-            candles: Default::default(),
+            candles,
             pool,
+            shutdown,
             _completions: completions,
             _watcher,
-            _kafka_sub,
+            _source,
+            _book_poller,
         }
     }
 }
 
-pub fn init_methods(database_url: &str, redis_url: &str) -> RpcModule<WsContext> {
+pub fn init_methods(
+    database_url: &str,
+    redis_url: &str,
+    shutdown: &Shutdown,
+    candles: LiveCandles,
+) -> RpcModule<WsContext> {
     let manager = ConnectionManager::<PgConnection>::new(database_url);
     let pool = r2d2::Pool::builder()
         .max_size(50)
@@ -222,7 +269,12 @@ pub fn init_methods(database_url: &str, redis_url: &str) -> RpcModule<WsContext>
         .expect("Could not instantiate connection pool");
     let client = Client::open(redis_url).expect("Could not establish redis connection");
 
-    let mut module = RpcModule::new(WsContext::with_pool(pool, client));
+    let mut module = RpcModule::new(WsContext::with_pool(
+        pool,
+        client,
+        candles,
+        shutdown.clone(),
+    ));
 
     module
         .register_subscription(
@@ -242,6 +294,10 @@ pub fn init_methods(database_url: &str, redis_url: &str) -> RpcModule<WsContext>
         )
         .unwrap();
 
+    module
+        .register_method("get_order_book_snapshot", methods::order_book_snapshot)
+        .unwrap();
+
     module
         .register_subscription(
             "subscribe_candle_data",
@@ -274,7 +330,45 @@ pub fn init_methods(database_url: &str, redis_url: &str) -> RpcModule<WsContext>
 
 #[cfg(test)]
 mod tests {
-    // use super::*;
+    use super::*;
+    use crate::event_source::ChannelSource;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn price_updates_with_bad_timestamps_are_skipped() {
+        // Nothing listens on either, the candle seed and the book poller just fail.
+        let manager = ConnectionManager::<PgConnection>::new("postgres://127.0.0.1:1/none");
+        let pool = r2d2::Pool::builder()
+            .connection_timeout(Duration::from_millis(10))
+            .build_unchecked(manager);
+        let client = Client::open("redis://127.0.0.1:1").unwrap();
+        let shutdown = Shutdown::default();
+        let (events, rx) = unbounded();
+        let ctx = WsContext::with_source(
+            pool,
+            client,
+            ChannelSource::new(rx),
+            LiveCandles::default(),
+            shutdown.clone(),
+        );
+        let mut prices = ctx.price_feed.subscribe();
+
+        events
+            .send(vec![
+                Event::CurrentPriceUpdate(1.0, "not a datetime".into()),
+                Event::CurrentPriceUpdate(2.0, "2026-03-01T00:00:00Z".into()),
+            ])
+            .unwrap();
+
+        let (price, ts) = tokio::time::timeout(Duration::from_secs(5), prices.recv())
+            .await
+            .expect("The watcher stopped")
+            .unwrap();
+        assert_eq!(price, 2.0);
+        assert_eq!(ts, "2026-03-01T00:00:00Z".parse::<DateTime<Utc>>().unwrap());
+
+        shutdown.close();
+    }
+
     // use jsonrpsee::{
     //     core::{
     //         client::ClientT,
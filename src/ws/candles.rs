@@ -0,0 +1,379 @@
+use crate::database::{BtcUsdPrice, CandleData};
+use crate::rpc::Interval;
+use bigdecimal::{BigDecimal, FromPrimitive, Zero};
+use chrono::{prelude::*, DurationRound};
+use diesel::prelude::{PgConnection, QueryResult};
+use log::debug;
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+};
+use tokio::sync::broadcast::{channel, Receiver, Sender};
+
+const CANDLE_CHANNEL_CAPACITY: usize = 10;
+
+/// Position sizes are stored scaled by 1e8, the same factor `refresh_candles` divides out.
+const SIZE_SCALE: u64 = 100_000_000;
+
+#[derive(Default)]
+struct State {
+    open: HashMap<Interval, CandleData>,
+    /// Closed hourly candles of the last day, `ONE_DAY_CHANGE` rolls these up with the open hour.
+    hours: VecDeque<CandleData>,
+}
+
+/// The open candle of every interval, built from the price ticks and filled trades the websocket
+/// watcher receives. Every change is pushed to the interval's subscribers, and `candle_data` uses
+/// it for the bar the archiver hasn't committed yet. Clones share the same candles.
+#[derive(Clone)]
+pub struct LiveCandles {
+    state: Arc<RwLock<State>>,
+    senders: Arc<HashMap<Interval, Sender<serde_json::Value>>>,
+}
+
+impl Default for LiveCandles {
+    fn default() -> Self {
+        let senders = Interval::MATERIALIZED
+            .into_iter()
+            .chain([Interval::ONE_DAY_CHANGE])
+            .map(|interval| (interval, channel(CANDLE_CHANNEL_CAPACITY).0))
+            .collect();
+
+        LiveCandles {
+            state: Default::default(),
+            senders: Arc::new(senders),
+        }
+    }
+}
+
+impl LiveCandles {
+    /// Start from the candles the archiver has materialized, so a restart in the middle of a bar
+    /// keeps its open, high and low.
+    pub fn seed(&self, conn: &mut PgConnection) -> QueryResult<()> {
+        let now = Utc::now();
+        let hour = now.duration_trunc(Interval::ONE_HOUR.duration()).unwrap();
+
+        let mut open = HashMap::new();
+        for interval in Interval::MATERIALIZED {
+            let start = now.duration_trunc(interval.duration()).unwrap();
+            if let Some(candle) = BtcUsdPrice::candles(conn, interval, start, Some(1), None)?.pop()
+            {
+                open.insert(interval, candle);
+            }
+        }
+
+        let hours = BtcUsdPrice::candles(
+            conn,
+            Interval::ONE_HOUR,
+            now - chrono::Duration::hours(24),
+            None,
+            None,
+        )?;
+
+        let mut state = self.write();
+        for (interval, candle) in open {
+            // Events received while we were querying are newer than the table.
+            state.open.entry(interval).or_insert(candle);
+        }
+        state.hours = hours.into_iter().filter(|c| c.start < hour).collect();
+
+        Ok(())
+    }
+
+    /// Apply a `CurrentPriceUpdate` tick.
+    pub fn price(&self, price: f64, ts: DateTime<Utc>) {
+        let Some(price) = BigDecimal::from_f64(price) else {
+            return;
+        };
+        let price = price.round(2);
+
+        self.apply(ts, &price, true, |candle| {
+            if price > candle.high {
+                candle.high = price.clone();
+            }
+            if price < candle.low {
+                candle.low = price.clone();
+            }
+            candle.close = price.clone();
+        });
+    }
+
+    /// Add a trade of `positionsize` at `entryprice` to the volume of the open candles. Callers only
+    /// pass order events whose status `OrderStatus::is_trade`, like the materialized candles count.
+    pub fn trade(&self, entryprice: f64, positionsize: f64, ts: DateTime<Utc>) {
+        let (Some(price), Some(size)) = (
+            BigDecimal::from_f64(entryprice),
+            BigDecimal::from_f64(positionsize),
+        ) else {
+            return;
+        };
+        let usd = &size / &BigDecimal::from(SIZE_SCALE);
+        let btc = if price.is_zero() {
+            BigDecimal::zero()
+        } else {
+            &usd / &price
+        };
+
+        self.apply(ts, &price.round(2), false, |candle| {
+            candle.trades += 1;
+            candle.usd_volume += &usd;
+            candle.btc_volume += &btc;
+        });
+    }
+
+    /// The open candle of `interval`, `ONE_DAY_CHANGE` is the last 24 hours rolled up.
+    pub fn current(&self, interval: Interval) -> Option<CandleData> {
+        let state = self.read();
+
+        match interval {
+            Interval::ONE_DAY_CHANGE => day_change(&state, Utc::now()),
+            _ => state.open.get(&interval).cloned(),
+        }
+    }
+
+    /// Every change to the `interval` candle, serialized the way `candle_data` returns it.
+    pub fn subscribe(&self, interval: Interval) -> Receiver<serde_json::Value> {
+        self.senders[&interval].subscribe()
+    }
+
+    /// Put the live bar into a page of materialized `candles` read from `since` on: replace the
+    /// row for the same bucket, or append it when the page ends before it and still has room.
+    pub fn overlay(
+        &self,
+        interval: Interval,
+        candles: &mut Vec<CandleData>,
+        since: DateTime<Utc>,
+        limit: i64,
+        offset: i64,
+    ) {
+        if interval == Interval::ONE_DAY_CHANGE {
+            return;
+        }
+        let Some(live) = self.current(interval) else {
+            return;
+        };
+
+        if let Some(candle) = candles.iter_mut().find(|c| c.start == live.start) {
+            *candle = live;
+            return;
+        }
+
+        let follows_page = match candles.last() {
+            Some(last) => last.start < live.start,
+            None => offset == 0 && since.duration_trunc(interval.duration()).unwrap() <= live.start,
+        };
+        if follows_page && (candles.len() as i64) < limit {
+            candles.push(live);
+        }
+    }
+
+    /// Run `update` on the candle of every interval containing `ts`, opening a new one when `ts`
+    /// is past the current bar. Ticks open a bar at their own price, trades at the last close.
+    fn apply(
+        &self,
+        ts: DateTime<Utc>,
+        price: &BigDecimal,
+        tick: bool,
+        update: impl Fn(&mut CandleData),
+    ) {
+        let now = Utc::now();
+        let mut changed = Vec::new();
+        let mut state = self.write();
+
+        for interval in Interval::MATERIALIZED {
+            let start = ts.duration_trunc(interval.duration()).unwrap();
+
+            let previous = match state.open.get(&interval) {
+                Some(candle) if candle.start > start => {
+                    debug!("Ignoring late {:?} candle update at {}", interval, ts);
+                    continue;
+                }
+                Some(candle) if candle.start == start => None,
+                Some(candle) => Some(candle.clone()),
+                None => None,
+            };
+
+            let candle = match previous {
+                Some(closed) => {
+                    let open = if tick {
+                        price.clone()
+                    } else {
+                        closed.close.clone()
+                    };
+                    if interval == Interval::ONE_HOUR {
+                        state.hours.push_back(closed);
+                    }
+                    state
+                        .open
+                        .insert(interval, new_candle(interval, start, open));
+                    state.open.get_mut(&interval).unwrap()
+                }
+                None => state
+                    .open
+                    .entry(interval)
+                    .or_insert_with(|| new_candle(interval, start, price.clone())),
+            };
+
+            update(candle);
+            candle.updated_at = now;
+            changed.push((interval, candle.clone()));
+        }
+
+        let day_start = now - chrono::Duration::hours(24);
+        while state.hours.front().map_or(false, |c| c.start < day_start) {
+            state.hours.pop_front();
+        }
+        if changed.iter().any(|(i, _)| *i == Interval::ONE_HOUR) {
+            if let Some(candle) = day_change(&state, now) {
+                changed.push((Interval::ONE_DAY_CHANGE, candle));
+            }
+        }
+        drop(state);
+
+        for (interval, candle) in changed {
+            let Ok(value) = serde_json::to_value(vec![candle]) else {
+                continue;
+            };
+            // Only fails when nobody is subscribed to this interval.
+            let _ = self.senders[&interval].send(value);
+        }
+    }
+
+    fn read(&self) -> RwLockReadGuard<'_, State> {
+        self.state.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, State> {
+        self.state.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+fn new_candle(interval: Interval, start: DateTime<Utc>, open: BigDecimal) -> CandleData {
+    CandleData {
+        updated_at: Utc::now(),
+        start,
+        end: start + interval.duration(),
+        low: open.clone(),
+        high: open.clone(),
+        open: open.clone(),
+        close: open,
+        resolution: interval.interval_sql().to_string(),
+        btc_volume: BigDecimal::zero(),
+        trades: 0,
+        usd_volume: BigDecimal::zero(),
+    }
+}
+
+/// Roll up the hourly candles starting in the 24 hours before `now`, like the `ONE_DAY_CHANGE`
+/// query does for `candle_data`.
+fn day_change(state: &State, now: DateTime<Utc>) -> Option<CandleData> {
+    let since = now - chrono::Duration::hours(24) + chrono::Duration::seconds(5);
+    let hours: Vec<&CandleData> = state
+        .hours
+        .iter()
+        .chain(state.open.get(&Interval::ONE_HOUR))
+        .filter(|c| c.start >= since)
+        .collect();
+
+    let (first, last) = (hours.first()?, hours.last()?);
+    let mut candle = CandleData {
+        updated_at: now,
+        start: since,
+        end: since + Interval::ONE_DAY_CHANGE.duration(),
+        low: first.low.clone(),
+        high: first.high.clone(),
+        open: first.open.clone(),
+        close: last.close.clone(),
+        resolution: Interval::ONE_DAY_CHANGE.interval_sql().to_string(),
+        btc_volume: BigDecimal::zero(),
+        trades: 0,
+        usd_volume: BigDecimal::zero(),
+    };
+    for hour in hours {
+        if hour.low < candle.low {
+            candle.low = hour.low.clone();
+        }
+        if hour.high > candle.high {
+            candle.high = hour.high.clone();
+        }
+        candle.btc_volume += &hour.btc_volume;
+        candle.usd_volume += &hour.usd_volume;
+        candle.trades += hour.trades;
+    }
+
+    Some(candle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(minute: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2026, 1, 1, 12, 0, 0).unwrap() + chrono::Duration::minutes(minute)
+    }
+
+    #[test]
+    fn ticks_build_and_roll_bars() {
+        let candles = LiveCandles::default();
+        let mut rx = candles.subscribe(Interval::FIVE_MINUTE);
+
+        candles.price(100.0, at(1));
+        candles.price(120.0, at(2));
+        candles.price(90.0, at(3));
+        candles.trade(100.0, 50_000_000.0, at(3));
+
+        let bar = candles.current(Interval::FIVE_MINUTE).unwrap();
+        assert_eq!(bar.start, at(0));
+        assert_eq!(bar.end, at(5));
+        assert_eq!(bar.open, BigDecimal::from(100));
+        assert_eq!(bar.high, BigDecimal::from(120));
+        assert_eq!(bar.low, BigDecimal::from(90));
+        assert_eq!(bar.close, BigDecimal::from(90));
+        assert_eq!(bar.trades, 1);
+        assert_eq!(bar.usd_volume, "0.5".parse::<BigDecimal>().unwrap());
+        assert_eq!(bar.btc_volume, "0.005".parse::<BigDecimal>().unwrap());
+
+        // One push per change.
+        for _ in 0..4 {
+            assert!(rx.try_recv().is_ok());
+        }
+        assert!(rx.try_recv().is_err());
+
+        // A trade past the bar opens the next one at the last close, a late tick is dropped.
+        candles.trade(95.0, 1.0, at(6));
+        candles.price(130.0, at(4));
+        let bar = candles.current(Interval::FIVE_MINUTE).unwrap();
+        assert_eq!(bar.start, at(5));
+        assert_eq!(bar.open, BigDecimal::from(90));
+        assert_eq!(bar.high, BigDecimal::from(90));
+        assert_eq!(bar.trades, 1);
+    }
+
+    #[test]
+    fn overlay_replaces_or_appends_the_open_bar() {
+        let candles = LiveCandles::default();
+        candles.price(100.0, at(11));
+
+        let stored = new_candle(Interval::FIVE_MINUTE, at(5), BigDecimal::from(1));
+        let mut page = vec![stored.clone()];
+        candles.overlay(Interval::FIVE_MINUTE, &mut page, at(5), 10, 0);
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[1].start, at(10));
+
+        let mut page = vec![
+            stored.clone(),
+            new_candle(Interval::FIVE_MINUTE, at(10), 1.into()),
+        ];
+        candles.overlay(Interval::FIVE_MINUTE, &mut page, at(5), 10, 0);
+        assert_eq!(page.len(), 2);
+        assert_eq!(page[1].open, BigDecimal::from(100));
+
+        // A full page, or one that ends before the live bar, is left alone.
+        let mut page = vec![stored];
+        candles.overlay(Interval::FIVE_MINUTE, &mut page, at(5), 1, 0);
+        assert_eq!(page.len(), 1);
+        let mut page = vec![];
+        candles.overlay(Interval::FIVE_MINUTE, &mut page, at(5), 10, 3);
+        assert!(page.is_empty());
+    }
+}
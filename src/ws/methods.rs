@@ -1,31 +1,59 @@
-#![allow(warnings)]
 use crate::{
-    database::{Ask, Bid, BtcUsdPrice, OrderBook, TraderOrder},
     error::ApiError,
-    rpc::{order_book, CandleSubscription, Interval},
+    order_book::{BookMessage, BookView, OrderBookArgs, MAX_BOOK_DEPTH},
+    rpc::{error::RpcError, CandleSubscription},
 };
-use chrono::prelude::*;
 use jsonrpsee::{
     server::{logger::Params, SubscriptionSink},
-    types::error::SubscriptionResult,
+    types::{error::SubscriptionResult, ErrorObjectOwned},
 };
 use log::{error, info, warn};
 use serde::Serialize;
 use std::{sync::Arc, time::Duration};
 use tokio::{
-    sync::broadcast::{channel, error::TryRecvError, Receiver},
+    sync::{
+        broadcast::{
+            error::{RecvError, TryRecvError},
+            Receiver,
+        },
+        watch,
+    },
     task::JoinHandle,
     time::sleep,
 };
 
 use super::WsContext;
 
-fn pipe<T>(task_name: String, mut rx: Receiver<T>, mut sink: SubscriptionSink) -> JoinHandle<()>
+/// Close the subscription with a final notification telling the client the server is going away.
+fn close_on_shutdown(task_name: &str, sink: SubscriptionSink) {
+    info!("{}: server shutting down, closing subscription.", task_name);
+    sink.close(ErrorObjectOwned::from(RpcError::ShuttingDown));
+}
+
+/// Sleep for `duration`, waking up early once shutdown starts.
+async fn pause(duration: Duration, shutdown: &mut watch::Receiver<bool>) {
+    tokio::select! {
+        _ = sleep(duration) => {}
+        Ok(()) = shutdown.changed() => {}
+    }
+}
+
+fn pipe<T>(
+    task_name: String,
+    mut rx: Receiver<T>,
+    mut sink: SubscriptionSink,
+    mut shutdown: watch::Receiver<bool>,
+) -> JoinHandle<()>
 where
     T: Clone + Serialize + std::marker::Send + 'static,
 {
     tokio::task::spawn(async move {
         loop {
+            if *shutdown.borrow() {
+                close_on_shutdown(&task_name, sink);
+                return;
+            }
+
             match rx.try_recv() {
                 Ok(mesg) => {
                     if let Err(e) = sink.send(&mesg) {
@@ -41,7 +69,7 @@ where
                     warn!("{}: Channel is lagging by {} messages", task_name, by);
                 }
                 Err(TryRecvError::Empty) => {
-                    sleep(Duration::from_millis(100)).await;
+                    pause(Duration::from_millis(100), &mut shutdown).await;
                 }
             }
 
@@ -62,65 +90,36 @@ pub(super) fn candle_update(
 
     let CandleSubscription { interval } = params.parse()?;
 
-    let spawn = match ctx.candles.read() {
-        Ok(r) => !r.contains_key(&interval),
-        Err(e) => {
-            sink.send(&format!("RwLock poisoned!"));
-            return Ok(());
+    let mut rx = ctx.candles.subscribe(interval);
+    if let Some(candle) = ctx.candles.current(interval) {
+        if let Err(e) = sink.send(&vec![candle]) {
+            error!("Error sending candle updates: {:?}", e);
         }
-    };
-
-    if spawn {
-        info!("SPAWNING new subscriber for {:?}", interval);
-        let Ok(mut l) = ctx.candles.write() else {
-            sink.send(&"Write Lock poisoned!");
-            return Ok(());
-        };
-        let (tx, _) = channel(10);
-        l.insert(interval, tx.clone());
-
-        let c = ctx.clone();
-        let _: JoinHandle<Result<(), ApiError>> = tokio::task::spawn(async move {
-            loop {
-                let mut conn = c.pool.get()?;
-                let since: DateTime<Utc> = match interval {
-                    Interval::ONE_DAY_CHANGE => Utc::now() - chrono::Duration::hours(24),
-                    _ => Utc::now() - chrono::Duration::milliseconds(250),
-                };
-                let candles = BtcUsdPrice::candles(&mut conn, interval, since, None, None)?;
-
-                if candles.len() > 0 {
-                    let result = serde_json::to_value(&candles)?;
-                    if let Err(e) = tx.send(result) {
-                        error!("Error sending candle updates: {:?}", e);
-                    }
-                    match interval {
-                        Interval::ONE_DAY_CHANGE => {
-                            sleep(Duration::from_millis(1000)).await;
-                        }
-                        _ => {
-                            sleep(Duration::from_millis(250)).await;
-                        }
-                    };
-                } else {
-                    sleep(Duration::from_millis(250)).await;
-                }
-            }
-            Ok(())
-        });
     }
 
-    let Ok(l) = ctx.candles.read() else {
-        sink.send(&"Failed to acquire rx candles channel");
-        return Ok(());
-    };
-
-    let mut rx = l.get(&interval).unwrap().subscribe();
+    let mut shutdown = ctx.shutdown.subscribe();
     let _result = tokio::task::spawn(async move {
         loop {
-            let Ok(msg) = rx.recv().await else {
-                error!("Recv channel broken!");
-                break;
+            if *shutdown.borrow() {
+                close_on_shutdown("candle_update", sink);
+                return;
+            }
+
+            let msg = tokio::select! {
+                msg = rx.recv() => msg,
+                Ok(()) = shutdown.changed() => continue,
+            };
+            let msg = match msg {
+                Ok(msg) => msg,
+                // Every message is the whole bar, skipping some only loses intermediate states.
+                Err(RecvError::Lagged(by)) => {
+                    warn!("candle_update: Channel is lagging by {} messages", by);
+                    continue;
+                }
+                Err(RecvError::Closed) => {
+                    error!("Recv channel broken!");
+                    break;
+                }
             };
 
             if let Err(e) = sink.send(&msg) {
@@ -138,66 +137,123 @@ pub(super) fn candle_update(
     Ok(())
 }
 
+/// Parse the optional `OrderBookArgs` of the order book methods, which default to every level
+/// ungrouped.
+fn book_view(params: &Params<'_>) -> Result<BookView, RpcError> {
+    let args = params
+        .parse::<Option<OrderBookArgs>>()
+        .map_err(|e| RpcError::InvalidArgument(format!("{:?}", e)))?;
+
+    Ok(BookView::new(args.unwrap_or_default(), MAX_BOOK_DEPTH)?)
+}
+
 pub(super) fn spawn_order_book(
-    _params: Params<'_>,
+    params: Params<'_>,
     mut sink: SubscriptionSink,
     ctx: Arc<WsContext>,
 ) -> SubscriptionResult {
-    let mut rx = ctx.order_book.subscribe();
+    let view = match book_view(&params) {
+        Ok(view) => view,
+        Err(e) => {
+            sink.reject(e)?;
+            return Ok(());
+        }
+    };
+    let ((seq, mut book), mut rx) = ctx.order_book.subscribe();
+    let mut shutdown = ctx.shutdown.subscribe();
     sink.accept()?;
 
-    let _: JoinHandle<Result<(), ApiError>> = tokio::task::spawn(async move {
+    tokio::task::spawn(async move {
+        // Deltas arrive for the whole book, the subscriber gets the difference they make to
+        // its view. Every delta is forwarded, even one that changes nothing in the view, so
+        // that seq keeps counting up by one.
+        let mut last_seq = seq;
+        let mut seen = book.view(view);
+        if let Err(e) = sink.send(&seen.snapshot(seq)) {
+            error!("Error sending orderbook snapshot: {:?}", e);
+            return;
+        }
+
         loop {
-            match rx.try_recv() {
-                Ok(mesg) => {
-                    let mut conn = ctx.pool.get()?;
-                    let mut redis_conn = ctx.client.get_connection().expect("REDIS connection.");
-                    // let mut orders = order_book(&mut redis_conn);
-                    // orders.add_order(mesg);
-                    let result = serde_json::to_value(&mesg)?;
-
-                    if let Err(e) = sink.send(&result) {
-                        error!("Error sending orderbook updates: {:?}", e);
-                        break;
-                    }
-                    sleep(Duration::from_secs(5)).await;
-                }
-                Err(TryRecvError::Closed) => {
-                    info!("order_book: Channel closed");
-                    break;
+            if *shutdown.borrow() {
+                close_on_shutdown("order_book", sink);
+                return;
+            }
+
+            let msg = tokio::select! {
+                msg = rx.recv() => msg,
+                Ok(()) = shutdown.changed() => continue,
+            };
+            let msg = match msg {
+                // Already part of a snapshot sent after a lag.
+                Ok(msg) if msg.seq() <= last_seq => continue,
+                Ok(BookMessage::Delta { seq, changes }) => {
+                    book.apply(&changes);
+                    let next = book.view(view);
+                    let changes = seen.diff(&next);
+                    seen = next;
+                    BookMessage::Delta { seq, changes }
                 }
-                Err(TryRecvError::Lagged(by)) => {
+                Ok(msg) => msg,
+                // The deltas we skipped are gone, start the subscriber over from a fresh book.
+                Err(RecvError::Lagged(by)) => {
                     warn!("order_book: Channel is lagging by {} messages", by);
+                    let (seq, levels) = ctx.order_book.book();
+                    book = levels;
+                    seen = book.view(view);
+                    seen.snapshot(seq)
                 }
-                Err(TryRecvError::Empty) => {
-                    sleep(Duration::from_millis(200)).await;
+                Err(RecvError::Closed) => {
+                    info!("order_book: Channel closed");
+                    break;
                 }
+            };
+
+            last_seq = msg.seq();
+            if let Err(e) = sink.send(&msg) {
+                error!("Error sending orderbook updates: {:?}", e);
+                break;
             }
             if sink.is_closed() {
                 info!("order_book: subscriber closed, exiting.");
                 break;
             }
         }
-        Ok(())
     });
 
     Ok(())
 }
 
+/// The order book and the sequence number of the last `subscribe_order_book` delta it includes.
+pub(super) fn order_book_snapshot(
+    params: Params<'_>,
+    ctx: &WsContext,
+) -> Result<BookMessage, jsonrpsee::core::Error> {
+    let view = book_view(&params)?;
+
+    Ok(ctx.order_book.snapshot(view))
+}
+
 pub(super) fn heartbeat(
     _params: Params<'_>,
     mut sink: SubscriptionSink,
-    _ctx: Arc<WsContext>,
+    ctx: Arc<WsContext>,
 ) -> SubscriptionResult {
+    let mut shutdown = ctx.shutdown.subscribe();
     sink.accept()?;
 
-    let _: JoinHandle<Result<(), ApiError>> = tokio::task::spawn(async move {
+    let _heartbeat: JoinHandle<Result<(), ApiError>> = tokio::task::spawn(async move {
         loop {
-            let result = serde_json::to_value(&"BEAT")?;
+            if *shutdown.borrow() {
+                close_on_shutdown("heartbeat", sink);
+                return Ok(());
+            }
+
+            let result = serde_json::to_value("BEAT")?;
             if let Err(e) = sink.send(&result) {
                 error!("Error sending hearbeat: {:?}", e);
             }
-            sleep(Duration::from_secs(5)).await;
+            pause(Duration::from_secs(5), &mut shutdown).await;
         }
         // Ok(())
     });
@@ -213,7 +269,7 @@ pub(super) fn recent_trades(
     let rx = ctx.recent_trades.subscribe();
     sink.accept()?;
 
-    let _ = pipe("Recent Trades".into(), rx, sink);
+    pipe("Recent Trades".into(), rx, sink, ctx.shutdown.subscribe());
 
     Ok(())
 }
@@ -226,7 +282,7 @@ pub(super) fn spawn_live_price_data(
     let rx = ctx.price_feed.subscribe();
     sink.accept()?;
 
-    let _ = pipe("Live Price Feed".into(), rx, sink);
+    pipe("Live Price Feed".into(), rx, sink, ctx.shutdown.subscribe());
 
     Ok(())
 }
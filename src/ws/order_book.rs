@@ -0,0 +1,189 @@
+use crate::order_book::{BookLevels, BookMessage, BookView};
+use crate::rpc::shutdown::Shutdown;
+use log::{debug, error, info};
+use std::{
+    sync::{Arc, Mutex, MutexGuard},
+    time::Duration,
+};
+use tokio::sync::broadcast::{channel, Receiver, Sender};
+
+const BOOK_CHANNEL_CAPACITY: usize = 64;
+
+struct State {
+    seq: u64,
+    levels: BookLevels,
+}
+
+/// The aggregated order book the archiver keeps in redis, polled and turned into a sequenced
+/// stream of level deltas. Clones share the same book.
+#[derive(Clone)]
+pub struct OrderBookFeed {
+    state: Arc<Mutex<State>>,
+    updates: Sender<BookMessage>,
+}
+
+impl Default for OrderBookFeed {
+    fn default() -> Self {
+        OrderBookFeed {
+            state: Arc::new(Mutex::new(State {
+                seq: 0,
+                levels: BookLevels::default(),
+            })),
+            updates: channel(BOOK_CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl OrderBookFeed {
+    /// Poll redis every `interval` on a thread of its own until `shutdown` is closed. The
+    /// connection is kept between polls and only reopened after an error.
+    pub fn spawn(
+        &self,
+        client: redis::Client,
+        interval: Duration,
+        shutdown: Shutdown,
+    ) -> std::thread::JoinHandle<()> {
+        let feed = self.clone();
+
+        std::thread::spawn(move || {
+            let mut conn = None;
+            while !shutdown.is_closing() {
+                if conn.is_none() {
+                    match client.get_connection() {
+                        Ok(c) => conn = Some(c),
+                        Err(e) => error!("Failed to connect to the order book: {:?}", e),
+                    }
+                }
+                if let Some(c) = conn.as_mut() {
+                    if let Err(e) = feed.poll(c) {
+                        error!("Failed to poll the order book: {:?}", e);
+                        conn = None;
+                    }
+                }
+                std::thread::sleep(interval);
+            }
+            info!("Order book poller stopped");
+        })
+    }
+
+    /// Read the book from redis and publish what changed since the last poll.
+    pub fn poll(&self, conn: &mut redis::Connection) -> redis::RedisResult<()> {
+        let levels = BookLevels::load(conn)?;
+        self.update(levels);
+
+        Ok(())
+    }
+
+    /// Replace the book with `levels`, broadcasting the difference as the next delta.
+    pub fn update(&self, levels: BookLevels) {
+        let mut state = self.lock();
+        let changes = state.levels.diff(&levels);
+        if changes.is_empty() {
+            return;
+        }
+
+        state.seq += 1;
+        state.levels = levels;
+        // Sent under the lock, so a snapshot taken by `subscribe` is never followed by a delta
+        // it already contains.
+        if let Err(e) = self.updates.send(BookMessage::Delta {
+            seq: state.seq,
+            changes,
+        }) {
+            debug!("No order book subscribers present {:?}", e);
+        }
+    }
+
+    /// The book as `view` sees it and the sequence number of the last delta applied to it.
+    pub fn snapshot(&self, view: BookView) -> BookMessage {
+        let (seq, levels) = self.book();
+        levels.view(view).snapshot(seq)
+    }
+
+    /// The ungrouped book and its sequence number.
+    pub fn book(&self) -> (u64, BookLevels) {
+        let state = self.lock();
+        (state.seq, state.levels.clone())
+    }
+
+    /// The ungrouped book together with a receiver for every delta after it.
+    pub fn subscribe(&self) -> ((u64, BookLevels), Receiver<BookMessage>) {
+        let state = self.lock();
+
+        ((state.seq, state.levels.clone()), self.updates.subscribe())
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order_book::{BookSide, LevelDelta};
+
+    #[test]
+    fn deltas_follow_the_snapshot_in_sequence() {
+        let feed = OrderBookFeed::default();
+
+        let mut levels = BookLevels::default();
+        levels.insert(BookSide::Bid, 4_200_000, 10.0);
+        levels.insert(BookSide::Bid, 4_210_000, 5.0);
+        levels.insert(BookSide::Ask, 4_300_000, 7.0);
+        feed.update(levels.clone());
+
+        let ((seq, book), mut rx) = feed.subscribe();
+        let BookMessage::Snapshot { seq, bid, ask } = book.snapshot(seq) else {
+            panic!("expected a snapshot");
+        };
+        assert_eq!(seq, 1);
+        assert_eq!(bid[0].price, 42_100.0);
+        assert_eq!(bid[1].price, 42_000.0);
+        assert_eq!(ask.len(), 1);
+
+        // Unchanged books don't consume a sequence number.
+        feed.update(levels.clone());
+        assert!(rx.try_recv().is_err());
+
+        levels.insert(BookSide::Bid, 4_200_000, 3.0);
+        levels.apply(&[LevelDelta {
+            side: BookSide::Ask,
+            price: 43_000.0,
+            positionsize: 0.0,
+        }]);
+        feed.update(levels);
+
+        let BookMessage::Delta { seq, changes } = rx.try_recv().unwrap() else {
+            panic!("expected a delta");
+        };
+        assert_eq!(seq, 2);
+        assert_eq!(
+            changes,
+            vec![
+                LevelDelta {
+                    side: BookSide::Bid,
+                    price: 42_000.0,
+                    positionsize: 3.0,
+                },
+                LevelDelta {
+                    side: BookSide::Ask,
+                    price: 43_000.0,
+                    positionsize: 0.0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn poller_stops_on_shutdown() {
+        let shutdown = Shutdown::default();
+        let client = redis::Client::open("redis://127.0.0.1:1").unwrap();
+        let handle =
+            OrderBookFeed::default().spawn(client, Duration::from_millis(10), shutdown.clone());
+        std::thread::sleep(Duration::from_millis(50));
+        shutdown.close();
+
+        handle.join().unwrap();
+    }
+}